@@ -0,0 +1,158 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use futures_util::{stream, StreamExt};
+use mongodb::bson::doc;
+
+use crate::config::Config;
+use crate::db::Collections;
+use crate::failed;
+use crate::ingest::{self, IngestError};
+use crate::models::bson_block_id;
+use crate::retry;
+use crate::source::{BlockFetch, BlockSource};
+
+#[derive(Debug, Default)]
+pub struct BackfillReport {
+    pub requested: usize,
+    pub ingested: usize,
+    pub already_present: usize,
+    pub missing: Vec<i32>,
+    pub failed: Vec<i32>,
+    // Being ingested by another task when the backfill reached them
+    pub in_progress: Vec<i32>,
+}
+
+#[derive(Debug, Default)]
+struct ChunkReport {
+    ingested: usize,
+    already_present: usize,
+    missing: Vec<i32>,
+    failed: Vec<i32>,
+    in_progress: Vec<i32>,
+}
+
+pub async fn run_backfill(
+    collections: &Collections,
+    source: &dyn BlockSource,
+    config: &Config,
+    start: i32,
+    end: i32,
+) -> BackfillReport {
+    let block_ids = config.fetch.block_ids_in_range(start, end);
+    let concurrency = config.backfill.concurrency;
+    let total = block_ids.len();
+    let done = AtomicUsize::new(0);
+
+    println!(
+        "Backfilling {} blocks between {} and {} with concurrency {}.",
+        total, start, end, concurrency
+    );
+
+    let chunk_reports: Vec<ChunkReport> = stream::iter(block_ids.chunks(config.backfill.chunk_size))
+        .map(|chunk| {
+            let done = &done;
+            async move {
+                let report = backfill_chunk(source, collections, config, chunk).await;
+                let done = done.fetch_add(chunk.len(), Ordering::Relaxed) + chunk.len();
+                println!("Backfill progress: {}/{} blocks.", done, total);
+                report
+            }
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+
+    let mut report = BackfillReport { requested: total, ..Default::default() };
+    for chunk in chunk_reports {
+        report.ingested += chunk.ingested;
+        report.already_present += chunk.already_present;
+        report.missing.extend(chunk.missing);
+        report.failed.extend(chunk.failed);
+        report.in_progress.extend(chunk.in_progress);
+    }
+    report.missing.sort_unstable();
+    report.failed.sort_unstable();
+    report.in_progress.sort_unstable();
+    report
+}
+
+async fn backfill_chunk(
+    source: &dyn BlockSource,
+    collections: &Collections,
+    config: &Config,
+    chunk: &[i32],
+) -> ChunkReport {
+    let mut report = ChunkReport::default();
+
+    // Skip blocks we already have so reruns over the same range are cheap
+    let filter = doc! { "blockId": { "$in": chunk } };
+    let present: Vec<i32> = match collections.blocks.distinct("blockId", filter, None).await {
+        Ok(values) => values.iter().filter_map(bson_block_id).collect(),
+        Err(e) => {
+            eprintln!("Error checking existing blocks {}-{}: {}", chunk[0], chunk[chunk.len() - 1], e);
+            Vec::new()
+        }
+    };
+
+    for &block_id in chunk {
+        if present.contains(&block_id) {
+            report.already_present += 1;
+            continue;
+        }
+
+        let failure = match retry::fetch_with_retry(source, block_id, &config.retry).await {
+            Ok(BlockFetch::Found(block)) => {
+                match ingest::ingest_block(collections, &block).await {
+                    Ok(_) => {
+                        report.ingested += 1;
+                        None
+                    }
+                    // Whoever holds the claim stores it; not a failure to record
+                    Err(e @ IngestError::InProgress(_)) => {
+                        println!("{}, skipping it.", e);
+                        report.in_progress.push(block_id);
+                        None
+                    }
+                    Err(e) => {
+                        eprintln!("Error saving data for block ID {}: {}", block_id, e);
+                        Some((format!("Error saving block: {}", e), false))
+                    }
+                }
+            }
+            Ok(BlockFetch::Missing) => {
+                report.missing.push(block_id);
+                None
+            }
+            Err(e) => {
+                eprintln!("Error fetching data for block ID {}: {}", block_id, e);
+                Some((e.to_string(), !e.is_retryable()))
+            }
+        };
+
+        // Failed blocks are kept for a later `failed retry`
+        if let Some((error, permanent)) = failure {
+            report.failed.push(block_id);
+            if let Err(e) = failed::record_failure(&collections.failed, block_id, &error, permanent).await {
+                eprintln!("Error recording failure for block ID {}: {}", block_id, e);
+            }
+        }
+    }
+    report
+}
+
+impl BackfillReport {
+    pub fn print_summary(&self) {
+        println!(
+            "Backfill finished: {} requested, {} ingested, {} already present, {} missing upstream, {} failed, {} in progress elsewhere.",
+            self.requested, self.ingested, self.already_present, self.missing.len(), self.failed.len(), self.in_progress.len()
+        );
+        if !self.missing.is_empty() {
+            println!("Missing block IDs: {:?}", self.missing);
+        }
+        if !self.failed.is_empty() {
+            println!("Failed block IDs: {:?}", self.failed);
+        }
+        if !self.in_progress.is_empty() {
+            println!("Block IDs being ingested by another task, rerun the backfill if they stay missing: {:?}", self.in_progress);
+        }
+    }
+}
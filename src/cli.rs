@@ -0,0 +1,112 @@
+use std::path::PathBuf;
+use clap::{Parser, Subcommand, ValueEnum};
+
+#[derive(Debug, Parser)]
+#[command(name = "xenvoterleaderboardapi", about = "XEN voter leaderboard ingestion and API server")]
+pub struct Cli {
+    /// Configuration file; takes precedence over XENVOTER_CONFIG
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start the HTTP and WebSocket servers together with the ingestion loop (default)
+    Serve,
+    /// Run the ingestion loop only, without opening any ports
+    Ingest,
+    /// Fetch and store every block in [from, to], then exit
+    Backfill {
+        #[arg(long)]
+        from: i32,
+        #[arg(long)]
+        to: i32,
+        /// Number of chunks fetched at the same time (overrides backfill.concurrency)
+        #[arg(long)]
+        concurrency: Option<usize>,
+    },
+    /// Recompute the pubkey_stats collection and the hourly vote buckets from the stored blocks.
+    /// Votes ingested while it runs are lost, so stop serve and ingest first
+    RebuildLeaderboard,
+    /// Recompute the forks collection from the stored blocks
+    RebuildForks,
+    /// Store a snapshot of the current leaderboard
+    Snapshot,
+    /// Create or migrate the indexes the API queries rely on and report index drift
+    Reindex {
+        /// Only report drift without changing any index
+        #[arg(long)]
+        check: bool,
+    },
+    /// Write stored blocks or the leaderboard as newline-delimited JSON
+    Export {
+        #[arg(value_enum)]
+        what: ExportKind,
+        /// First block ID to include
+        #[arg(long)]
+        from: Option<i32>,
+        /// Last block ID to include
+        #[arg(long)]
+        to: Option<i32>,
+        /// Output file; defaults to stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Inspect or move the ingestion cursor
+    Cursor {
+        #[command(subcommand)]
+        action: CursorAction,
+    },
+    /// List block IDs missing from the blocks collection
+    Gaps {
+        /// First block ID to scan; defaults to fetch.start_block_id
+        #[arg(long)]
+        from: Option<i32>,
+        /// Last block ID to scan; defaults to the latest stored block
+        #[arg(long)]
+        to: Option<i32>,
+        /// Queue the missing blocks for `failed retry`
+        #[arg(long)]
+        enqueue: bool,
+    },
+    /// Inspect or retry blocks the fetcher failed to ingest
+    Failed {
+        #[command(subcommand)]
+        action: FailedAction,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ExportKind {
+    Blocks,
+    Leaderboard,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CursorAction {
+    /// Print the stored cursor
+    Show,
+    /// Make the fetcher continue at the given block ID
+    Rewind { block_id: i32 },
+    /// Forget the cursor so the fetcher starts over
+    Reset,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum FailedAction {
+    /// Print the recorded failures, one JSON document per line
+    List {
+        /// Include failures recorded as permanent
+        #[arg(long)]
+        all: bool,
+    },
+    /// Fetch the recorded failures again
+    Retry {
+        /// Include failures recorded as permanent
+        #[arg(long)]
+        all: bool,
+    },
+}
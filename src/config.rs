@@ -0,0 +1,456 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::events::LagPolicy;
+use crate::fetch::FetchMode;
+use crate::source::SourceKind;
+
+// Environment variable pointing at the configuration file
+const CONFIG_PATH_VAR: &str = "XENVOTER_CONFIG";
+// Configuration file looked up in the working directory when XENVOTER_CONFIG is not set
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to read configuration file {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("Failed to parse configuration file {0}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+    #[error("Invalid value {1:?} for environment variable {0}")]
+    InvalidOverride(&'static str, String),
+    #[error("Invalid configuration: {0}")]
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub mongo: MongoConfig,
+    pub upstream: UpstreamConfig,
+    pub fetch: FetchConfig,
+    pub backfill: BackfillConfig,
+    pub retry: RetryConfig,
+    pub gaps: GapsConfig,
+    pub snapshots: SnapshotsConfig,
+    pub events: EventsConfig,
+    pub server: ServerConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MongoConfig {
+    pub uri: String,
+    pub database: String,
+    pub blocks_collection: String,
+    pub state_collection: String,
+    pub pubkey_stats_collection: String,
+    pub failed_collection: String,
+    pub forks_collection: String,
+    pub hourly_stats_collection: String,
+    pub snapshots_collection: String,
+    pub snapshot_rows_collection: String,
+    pub events_collection: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamConfig {
+    pub kind: SourceKind,
+    // Base URL; the block ID is appended as the last path segment
+    pub url: String,
+    // File or directory of recorded blocks used by the replay source
+    pub replay_path: PathBuf,
+    // Number of synthetic blocks served by the mock source
+    pub mock_blocks: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FetchConfig {
+    pub mode: FetchMode,
+    pub start_block_id: i32,
+    pub end_block_id: i32,
+    pub block_increment: i32,
+    // Pause between two block fetches
+    pub request_interval_ms: u64,
+    // Pause before polling again once the follow mode has caught up with the tip
+    pub poll_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackfillConfig {
+    pub concurrency: usize,
+    pub chunk_size: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    // Total attempts per block, including the first one
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    // Fraction of each backoff delay that is randomized away, between 0 and 1
+    pub jitter: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GapsConfig {
+    // How often `serve` scans for missing blocks and re-fetches them; 0 disables the scan
+    pub repair_interval_secs: u64,
+    // Most missing blocks one scan enqueues and retries; larger gaps are left to `backfill`
+    pub max_repair_blocks: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnapshotsConfig {
+    // Snapshot the leaderboard after this many new or changed blocks; 0 disables snapshots
+    pub interval_blocks: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventsConfig {
+    // Size of the leaderboard top watched for leaderboard_update events
+    pub leaderboard_top_n: usize,
+    // Recent events kept in memory for clients that reconnect with resume_from
+    pub buffer_size: usize,
+    // Most events replayed to one reconnecting client before it is told to resync instead
+    pub max_replay: u64,
+    // Events queued per WebSocket client before it counts as lagging
+    pub channel_capacity: usize,
+    pub lag_policy: LagPolicy,
+    // Events waiting to be written to the event log; further ones are dropped from the log
+    pub log_queue_capacity: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: IpAddr,
+    pub http_port: u16,
+    pub ws_port: u16,
+    // How often WebSocket clients receive a heartbeat event; 0 disables heartbeats
+    pub heartbeat_interval_secs: u64,
+    // Listener for the endpoints that change ingestion state, local only by default; port 0
+    // disables it
+    pub admin_bind_address: IpAddr,
+    pub admin_port: u16,
+}
+
+impl Default for MongoConfig {
+    fn default() -> Self {
+        MongoConfig {
+            uri: "mongodb://localhost:27017".to_string(),
+            database: "block_data".to_string(),
+            blocks_collection: "blocks".to_string(),
+            state_collection: "ingest_state".to_string(),
+            pubkey_stats_collection: "pubkey_stats".to_string(),
+            failed_collection: "failed_blocks".to_string(),
+            forks_collection: "forks".to_string(),
+            hourly_stats_collection: "pubkey_hourly_stats".to_string(),
+            snapshots_collection: "leaderboard_snapshots".to_string(),
+            snapshot_rows_collection: "leaderboard_snapshot_rows".to_string(),
+            events_collection: "ws_events".to_string(),
+        }
+    }
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        UpstreamConfig {
+            kind: SourceKind::Http,
+            url: "http://xolana.xen.network:4444/fetch_data".to_string(),
+            replay_path: PathBuf::from("replay"),
+            mock_blocks: 100,
+        }
+    }
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        FetchConfig {
+            mode: FetchMode::Window,
+            start_block_id: 27961401,
+            end_block_id: 27965401,
+            block_increment: 100,
+            request_interval_ms: 1000,
+            poll_interval_secs: 10,
+        }
+    }
+}
+
+impl Default for BackfillConfig {
+    fn default() -> Self {
+        BackfillConfig {
+            concurrency: 8,
+            chunk_size: 50,
+        }
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: 5,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            jitter: 0.5,
+        }
+    }
+}
+
+impl Default for GapsConfig {
+    fn default() -> Self {
+        GapsConfig {
+            repair_interval_secs: 600,
+            max_repair_blocks: 100,
+        }
+    }
+}
+
+impl Default for SnapshotsConfig {
+    fn default() -> Self {
+        SnapshotsConfig {
+            interval_blocks: 1000,
+        }
+    }
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        EventsConfig {
+            leaderboard_top_n: 100,
+            buffer_size: 1000,
+            max_replay: 10_000,
+            channel_capacity: 100,
+            lag_policy: LagPolicy::Resync,
+            log_queue_capacity: 10_000,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            http_port: 3031,
+            ws_port: 3030,
+            heartbeat_interval_secs: 30,
+            admin_bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            admin_port: 3032,
+        }
+    }
+}
+
+impl Config {
+    // Load the configuration file (if any), apply environment overrides and validate the result.
+    // An explicit `path` wins over XENVOTER_CONFIG, which wins over ./config.toml.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var(CONFIG_PATH_VAR).ok().map(PathBuf::from));
+        let mut config = match path {
+            Some(path) => Config::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Config::from_file(Path::new(DEFAULT_CONFIG_PATH))?,
+            None => Config::default(),
+        };
+        config.apply_env_overrides()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    fn apply_env_overrides(&mut self) -> Result<(), ConfigError> {
+        override_string("XENVOTER_MONGO_URI", &mut self.mongo.uri);
+        override_string("XENVOTER_MONGO_DATABASE", &mut self.mongo.database);
+        override_string("XENVOTER_MONGO_BLOCKS_COLLECTION", &mut self.mongo.blocks_collection);
+        override_string("XENVOTER_MONGO_STATE_COLLECTION", &mut self.mongo.state_collection);
+        override_string("XENVOTER_MONGO_PUBKEY_STATS_COLLECTION", &mut self.mongo.pubkey_stats_collection);
+        override_string("XENVOTER_MONGO_FAILED_COLLECTION", &mut self.mongo.failed_collection);
+        override_string("XENVOTER_MONGO_FORKS_COLLECTION", &mut self.mongo.forks_collection);
+        override_string("XENVOTER_MONGO_HOURLY_STATS_COLLECTION", &mut self.mongo.hourly_stats_collection);
+        override_string("XENVOTER_MONGO_SNAPSHOTS_COLLECTION", &mut self.mongo.snapshots_collection);
+        override_string("XENVOTER_MONGO_SNAPSHOT_ROWS_COLLECTION", &mut self.mongo.snapshot_rows_collection);
+        override_string("XENVOTER_MONGO_EVENTS_COLLECTION", &mut self.mongo.events_collection);
+        override_parsed("XENVOTER_UPSTREAM_KIND", &mut self.upstream.kind, SourceKind::parse)?;
+        override_string("XENVOTER_UPSTREAM_URL", &mut self.upstream.url);
+        override_parsed("XENVOTER_UPSTREAM_REPLAY_PATH", &mut self.upstream.replay_path, |v| Some(PathBuf::from(v)))?;
+        override_parsed("XENVOTER_UPSTREAM_MOCK_BLOCKS", &mut self.upstream.mock_blocks, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_FETCH_MODE", &mut self.fetch.mode, FetchMode::parse)?;
+        override_parsed("XENVOTER_START_BLOCK_ID", &mut self.fetch.start_block_id, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_END_BLOCK_ID", &mut self.fetch.end_block_id, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_BLOCK_INCREMENT", &mut self.fetch.block_increment, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_REQUEST_INTERVAL_MS", &mut self.fetch.request_interval_ms, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_POLL_INTERVAL_SECS", &mut self.fetch.poll_interval_secs, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_BACKFILL_CONCURRENCY", &mut self.backfill.concurrency, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_BACKFILL_CHUNK_SIZE", &mut self.backfill.chunk_size, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_RETRY_MAX_ATTEMPTS", &mut self.retry.max_attempts, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_RETRY_INITIAL_BACKOFF_MS", &mut self.retry.initial_backoff_ms, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_RETRY_MAX_BACKOFF_MS", &mut self.retry.max_backoff_ms, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_RETRY_JITTER", &mut self.retry.jitter, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_GAPS_REPAIR_INTERVAL_SECS", &mut self.gaps.repair_interval_secs, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_GAPS_MAX_REPAIR_BLOCKS", &mut self.gaps.max_repair_blocks, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_SNAPSHOTS_INTERVAL_BLOCKS", &mut self.snapshots.interval_blocks, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_EVENTS_LEADERBOARD_TOP_N", &mut self.events.leaderboard_top_n, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_EVENTS_BUFFER_SIZE", &mut self.events.buffer_size, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_EVENTS_MAX_REPLAY", &mut self.events.max_replay, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_EVENTS_CHANNEL_CAPACITY", &mut self.events.channel_capacity, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_EVENTS_LAG_POLICY", &mut self.events.lag_policy, LagPolicy::parse)?;
+        override_parsed("XENVOTER_EVENTS_LOG_QUEUE_CAPACITY", &mut self.events.log_queue_capacity, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_BIND_ADDRESS", &mut self.server.bind_address, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_HTTP_PORT", &mut self.server.http_port, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_WS_PORT", &mut self.server.ws_port, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_HEARTBEAT_INTERVAL_SECS", &mut self.server.heartbeat_interval_secs, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_ADMIN_BIND_ADDRESS", &mut self.server.admin_bind_address, |v| v.parse().ok())?;
+        override_parsed("XENVOTER_ADMIN_PORT", &mut self.server.admin_port, |v| v.parse().ok())?;
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: &str| Err(ConfigError::Invalid(message.to_string()));
+
+        if self.mongo.uri.is_empty() || self.mongo.database.is_empty() {
+            return invalid("mongo.uri and mongo.database must not be empty");
+        }
+        let collections = [
+            &self.mongo.blocks_collection,
+            &self.mongo.state_collection,
+            &self.mongo.pubkey_stats_collection,
+            &self.mongo.failed_collection,
+            &self.mongo.forks_collection,
+            &self.mongo.hourly_stats_collection,
+            &self.mongo.snapshots_collection,
+            &self.mongo.snapshot_rows_collection,
+            &self.mongo.events_collection,
+        ];
+        if collections.iter().any(|name| name.is_empty()) {
+            return invalid("mongo collection names must not be empty");
+        }
+        if collections.iter().enumerate().any(|(i, name)| collections[..i].contains(name)) {
+            return invalid("mongo collection names must all differ");
+        }
+        let is_http_url = self.upstream.url.starts_with("http://") || self.upstream.url.starts_with("https://");
+        if self.upstream.kind == SourceKind::Http && !is_http_url {
+            return invalid("upstream.url must be an http:// or https:// URL");
+        }
+        if self.upstream.kind == SourceKind::Replay && !self.upstream.replay_path.exists() {
+            return invalid("upstream.replay_path does not exist");
+        }
+        if self.fetch.block_increment <= 0 {
+            return invalid("fetch.block_increment must be positive");
+        }
+        if self.fetch.start_block_id > self.fetch.end_block_id {
+            return invalid("fetch.start_block_id must not be greater than fetch.end_block_id");
+        }
+        if self.backfill.concurrency == 0 || self.backfill.chunk_size == 0 {
+            return invalid("backfill.concurrency and backfill.chunk_size must be positive");
+        }
+        if self.retry.max_attempts == 0 {
+            return invalid("retry.max_attempts must be at least 1");
+        }
+        if self.retry.initial_backoff_ms > self.retry.max_backoff_ms {
+            return invalid("retry.initial_backoff_ms must not be greater than retry.max_backoff_ms");
+        }
+        if !(0.0..=1.0).contains(&self.retry.jitter) {
+            return invalid("retry.jitter must be between 0 and 1");
+        }
+        if self.gaps.repair_interval_secs > 0 && self.gaps.max_repair_blocks == 0 {
+            return invalid("gaps.max_repair_blocks must be positive when gap repair is enabled");
+        }
+        if self.events.leaderboard_top_n == 0 || self.events.buffer_size == 0 || self.events.channel_capacity == 0 || self.events.log_queue_capacity == 0 {
+            return invalid("events.leaderboard_top_n, events.buffer_size, events.channel_capacity and events.log_queue_capacity must be positive");
+        }
+        if self.server.http_port == self.server.ws_port {
+            return invalid("server.http_port and server.ws_port must differ");
+        }
+        if self.server.admin_port != 0 && (self.server.admin_port == self.server.http_port || self.server.admin_port == self.server.ws_port) {
+            return invalid("server.admin_port must differ from server.http_port and server.ws_port");
+        }
+        Ok(())
+    }
+}
+
+fn override_string(var: &'static str, target: &mut String) {
+    if let Ok(value) = std::env::var(var) {
+        *target = value;
+    }
+}
+
+fn override_parsed<T>(
+    var: &'static str,
+    target: &mut T,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<(), ConfigError> {
+    if let Ok(value) = std::env::var(var) {
+        *target = parse(&value).ok_or(ConfigError::InvalidOverride(var, value))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid_message(config: &Config) -> String {
+        match config.validate() {
+            Err(ConfigError::Invalid(message)) => message,
+            other => panic!("expected an invalid configuration, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        Config::default().validate().unwrap();
+    }
+
+    #[test]
+    fn example_config_parses_and_is_valid() {
+        let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(toml::from_str::<Config>("[fetch]\nblock_incremnt = 10\n").is_err());
+    }
+
+    #[test]
+    fn rejects_inconsistent_settings() {
+        let mut config = Config::default();
+        config.mongo.forks_collection = config.mongo.blocks_collection.clone();
+        assert!(invalid_message(&config).contains("must all differ"));
+
+        let mut config = Config::default();
+        config.fetch.block_increment = 0;
+        assert!(invalid_message(&config).contains("block_increment"));
+
+        let mut config = Config::default();
+        config.fetch.start_block_id = config.fetch.end_block_id + 1;
+        assert!(invalid_message(&config).contains("start_block_id"));
+
+        let mut config = Config::default();
+        config.retry.jitter = 1.5;
+        assert!(invalid_message(&config).contains("jitter"));
+
+        let mut config = Config::default();
+        config.server.ws_port = config.server.http_port;
+        assert!(invalid_message(&config).contains("ws_port"));
+
+        let mut config = Config::default();
+        config.server.admin_port = config.server.http_port;
+        assert!(invalid_message(&config).contains("admin_port"));
+        config.server.admin_port = 0;
+        config.validate().unwrap();
+    }
+}
@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use mongodb::{Collection, bson::{doc, Document, DateTime}};
+
+// The fetcher keeps a single state document under this ID
+const CURSOR_ID: &str = "fetcher";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IngestCursor {
+    #[serde(rename = "lastBlockId")]
+    pub last_block_id: Option<i32>,
+    #[serde(rename = "nextBlockId")]
+    pub next_block_id: i32,
+    #[serde(rename = "rangeStart")]
+    pub range_start: i32,
+    // None when following the upstream tip rather than cycling a fixed window
+    #[serde(rename = "rangeEnd")]
+    pub range_end: Option<i32>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<DateTime>,
+}
+
+impl IngestCursor {
+    pub fn new(next_block_id: i32, range_start: i32, range_end: Option<i32>) -> Self {
+        IngestCursor {
+            last_block_id: None,
+            next_block_id,
+            range_start,
+            range_end,
+            updated_at: None,
+        }
+    }
+
+    // Convert IngestCursor struct to a MongoDB Document
+    pub fn to_document(&self) -> Document {
+        let mut doc = Document::new();
+        doc.insert("_id", CURSOR_ID);
+        doc.insert("lastBlockId", self.last_block_id);
+        doc.insert("nextBlockId", self.next_block_id);
+        doc.insert("rangeStart", self.range_start);
+        doc.insert("rangeEnd", self.range_end);
+        doc.insert("updatedAt", DateTime::now());
+        doc
+    }
+}
+
+// A cursor document that cannot be read is an error rather than no cursor, so the stored
+// position is never silently replaced by a fresh one
+pub async fn load_cursor(state: &Collection<Document>) -> Result<Option<IngestCursor>, mongodb::error::Error> {
+    match state.find_one(doc! { "_id": CURSOR_ID }, None).await? {
+        Some(document) => Ok(Some(mongodb::bson::from_document(document)?)),
+        None => Ok(None),
+    }
+}
+
+// Overwrite the cursor unconditionally, creating it if it does not exist yet
+pub async fn store_cursor(state: &Collection<Document>, cursor: &IngestCursor) -> Result<(), mongodb::error::Error> {
+    let options = mongodb::options::ReplaceOptions::builder().upsert(true).build();
+    state.replace_one(doc! { "_id": CURSOR_ID }, cursor.to_document(), options).await?;
+    Ok(())
+}
+
+// Advance the cursor past `block_id`, recording it as ingested when `ingested` is true.
+// The update only applies if nobody rewound or reset the cursor while the block was in flight.
+pub async fn advance_cursor(
+    state: &Collection<Document>,
+    block_id: i32,
+    next_block_id: i32,
+    ingested: bool,
+) -> Result<bool, mongodb::error::Error> {
+    let mut set = doc! { "nextBlockId": next_block_id, "updatedAt": DateTime::now() };
+    if ingested {
+        set.insert("lastBlockId", block_id);
+    }
+    let filter = doc! { "_id": CURSOR_ID, "nextBlockId": block_id };
+    let result = state.update_one(filter, doc! { "$set": set }, None).await?;
+    Ok(result.matched_count == 1)
+}
+
+// Move the cursor so that `block_id` is the next block fetched, creating it if needed
+pub async fn rewind_cursor(
+    state: &Collection<Document>,
+    block_id: i32,
+    range_start: i32,
+    range_end: Option<i32>,
+) -> Result<(), mongodb::error::Error> {
+    let update = doc! {
+        "$set": { "nextBlockId": block_id, "updatedAt": DateTime::now() },
+        "$setOnInsert": { "lastBlockId": null, "rangeStart": range_start, "rangeEnd": range_end },
+    };
+    let options = mongodb::options::UpdateOptions::builder().upsert(true).build();
+    state.update_one(doc! { "_id": CURSOR_ID }, update, options).await?;
+    Ok(())
+}
+
+// Forget the cursor entirely; the fetcher starts over from the beginning of its range
+pub async fn reset_cursor(state: &Collection<Document>) -> Result<bool, mongodb::error::Error> {
+    let result = state.delete_one(doc! { "_id": CURSOR_ID }, None).await?;
+    Ok(result.deleted_count == 1)
+}
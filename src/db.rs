@@ -0,0 +1,58 @@
+use mongodb::{Collection, Database, bson::{doc, Document}};
+
+use crate::config::MongoConfig;
+
+// Handles to every collection the service reads or writes
+#[derive(Clone)]
+pub struct Collections {
+    pub blocks: Collection<Document>,
+    pub state: Collection<Document>,
+    pub pubkey_stats: Collection<Document>,
+    pub failed: Collection<Document>,
+    pub forks: Collection<Document>,
+    pub hourly_stats: Collection<Document>,
+    pub snapshots: Collection<Document>,
+    pub snapshot_rows: Collection<Document>,
+    pub events: Collection<Document>,
+}
+
+impl Collections {
+    pub fn new(db: &Database, settings: &MongoConfig) -> Self {
+        Collections {
+            blocks: db.collection(&settings.blocks_collection),
+            state: db.collection(&settings.state_collection),
+            pubkey_stats: db.collection(&settings.pubkey_stats_collection),
+            failed: db.collection(&settings.failed_collection),
+            forks: db.collection(&settings.forks_collection),
+            hourly_stats: db.collection(&settings.hourly_stats_collection),
+            snapshots: db.collection(&settings.snapshots_collection),
+            snapshot_rows: db.collection(&settings.snapshot_rows_collection),
+            events: db.collection(&settings.events_collection),
+        }
+    }
+}
+
+// An empty collection next to `target` for a rebuild to fill before `swap_in` replaces `target`
+// with it, so readers never see the target emptied or half-filled
+pub async fn staging_collection(target: &Collection<Document>) -> Result<Collection<Document>, mongodb::error::Error> {
+    let namespace = target.namespace();
+    let db = target.client().database(&namespace.db);
+    let name = format!("{}_rebuild", namespace.coll);
+    let staging = db.collection(&name);
+    // Left over from a rebuild that did not finish
+    staging.drop(None).await?;
+    db.create_collection(&name, None).await?;
+    Ok(staging)
+}
+
+// Atomically replace `target` with `staging`. Writes to `target` while the rebuild was running
+// are lost, so rebuilds run before ingestion starts or while it is stopped.
+pub async fn swap_in(staging: &Collection<Document>, target: &Collection<Document>) -> Result<(), mongodb::error::Error> {
+    let command = doc! {
+        "renameCollection": staging.namespace().to_string(),
+        "to": target.namespace().to_string(),
+        "dropTarget": true,
+    };
+    target.client().database("admin").run_command(command, None).await?;
+    Ok(())
+}
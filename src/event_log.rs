@@ -0,0 +1,130 @@
+use std::sync::Arc;
+use futures_util::StreamExt;
+use mongodb::{
+    Collection,
+    options::{FindOneAndUpdateOptions, FindOneOptions, FindOptions, ReturnDocument},
+    bson::{doc, Bson, Document},
+};
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+use crate::events::Event;
+use crate::metrics::Metrics;
+use crate::models::bson_block_id;
+
+// The state document holding the highest reserved event sequence number. It lives outside the
+// log, whose entries expire, so numbers are never handed out twice.
+const SEQ_COUNTER_ID: &str = "events";
+
+#[derive(Debug, Error)]
+pub enum LogError {
+    #[error("Event type cannot be stored: {0}")]
+    Kind(#[from] mongodb::bson::ser::Error),
+    #[error("Event payload cannot be stored: {0}")]
+    Payload(#[from] mongodb::bson::extjson::de::Error),
+    #[error("MongoDB error: {0}")]
+    Mongo(#[from] mongodb::error::Error),
+}
+
+// Write published events to the log in sequence order until the event bus goes away
+pub async fn run_event_log(log: Collection<Document>, mut rx: mpsc::Receiver<Arc<Event>>, metrics: Arc<Metrics>) {
+    while let Some(event) = rx.recv().await {
+        if let Err(e) = log_event(&log, &event).await {
+            eprintln!("Error logging event {}: {}", event.seq, e);
+            Metrics::increment(&metrics.event_log_dropped, 1);
+        }
+    }
+}
+
+// Highest sequence number reserved so far. Counters created by this version start at the highest
+// logged event, so numbering continues from logs written before the counter existed.
+pub async fn init_seq(state: &Collection<Document>, log: &Collection<Document>) -> Result<u64, mongodb::error::Error> {
+    let options = FindOneOptions::builder().sort(doc! { "_id": -1 }).build();
+    let logged = log.find_one(doc! {}, options).await?.and_then(|document| document.get_i64("_id").ok()).unwrap_or(0);
+    update_seq(state, doc! { "$max": { "seq": logged } }).await
+}
+
+// Record that sequence numbers up to `seq` may be handed out; the next run starts after them
+pub async fn reserve_seq(state: &Collection<Document>, seq: u64) -> Result<(), mongodb::error::Error> {
+    update_seq(state, doc! { "$max": { "seq": seq as i64 } }).await?;
+    Ok(())
+}
+
+async fn update_seq(state: &Collection<Document>, update: Document) -> Result<u64, mongodb::error::Error> {
+    let options = FindOneAndUpdateOptions::builder().upsert(true).return_document(ReturnDocument::After).build();
+    let document = state.find_one_and_update(doc! { "_id": SEQ_COUNTER_ID }, update, options).await?;
+    Ok(document.and_then(|document| document.get_i64("seq").ok()).unwrap_or(0) as u64)
+}
+
+// Sequence number of the last event logged for `block_id`
+pub async fn last_seq_for_block(log: &Collection<Document>, block_id: u32) -> Result<Option<u64>, mongodb::error::Error> {
+    let options = FindOneOptions::builder().sort(doc! { "_id": -1 }).build();
+    let document = log.find_one(doc! { "blockId": block_id as i64 }, options).await?;
+    Ok(document.and_then(|document| document.get_i64("_id").ok()).map(|seq| seq as u64))
+}
+
+// Up to `limit` logged events after `seq`, oldest first
+pub async fn events_after(log: &Collection<Document>, seq: u64, limit: usize) -> Result<Vec<Arc<Event>>, mongodb::error::Error> {
+    let options = FindOptions::builder().sort(doc! { "_id": 1 }).limit(limit as i64).build();
+    let mut cursor = log.find(doc! { "_id": { "$gt": seq as i64 } }, options).await?;
+    let mut events = Vec::new();
+    while let Some(document) = cursor.next().await {
+        match event_from_document(document?) {
+            Some(event) => events.push(Arc::new(event)),
+            None => eprintln!("Skipping malformed event log entry after seq {}", seq),
+        }
+    }
+    Ok(events)
+}
+
+async fn log_event(log: &Collection<Document>, event: &Event) -> Result<(), LogError> {
+    log.insert_one(event_document(event)?, None).await?;
+    Ok(())
+}
+
+fn event_document(event: &Event) -> Result<Document, LogError> {
+    Ok(doc! {
+        "_id": event.seq as i64,
+        "v": event.v as i32,
+        "type": mongodb::bson::to_bson(&event.kind)?,
+        "blockId": event.block_id.map(i64::from),
+        "ts": event.ts,
+        "payload": Bson::try_from(event.payload.clone())?,
+    })
+}
+
+fn event_from_document(document: Document) -> Option<Event> {
+    Some(Event {
+        v: document.get_i32("v").ok()? as u32,
+        kind: mongodb::bson::from_bson(document.get("type")?.clone()).ok()?,
+        seq: document.get_i64("_id").ok()? as u64,
+        block_id: document.get("blockId").and_then(bson_block_id).map(|id| id as u32),
+        ts: *document.get_datetime("ts").ok()?,
+        payload: document.get("payload").cloned().unwrap_or(Bson::Null).into_relaxed_extjson(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::DateTime;
+    use crate::events::EventKind;
+
+    fn event(payload: serde_json::Value) -> Event {
+        Event { v: 2, kind: EventKind::BlockIngested, seq: 7, block_id: Some(42), ts: DateTime::now(), payload }
+    }
+
+    #[test]
+    fn event_document_round_trips() {
+        let event = event(serde_json::json!({ "votes": 3 }));
+        let stored = event_from_document(event_document(&event).unwrap()).unwrap();
+        assert_eq!((stored.seq, stored.kind, stored.block_id), (7, EventKind::BlockIngested, Some(42)));
+        assert_eq!(stored.payload, serde_json::json!({ "votes": 3 }));
+    }
+
+    #[test]
+    fn unconvertible_payload_is_an_error_not_null() {
+        let event = event(serde_json::json!({ "$oid": "not an object id" }));
+        assert!(matches!(event_document(&event), Err(LogError::Payload(_))));
+    }
+}
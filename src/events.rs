@@ -0,0 +1,425 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use mongodb::{Collection, bson::{DateTime, Document}};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc, watch};
+
+use crate::config::EventsConfig;
+use crate::event_log;
+use crate::leaderboard::{self, LeaderboardRow};
+use crate::metrics::Metrics;
+
+// Version of the envelope below; bumped on incompatible changes. Events replayed from the log
+// keep the version they were published with.
+//   1: leaderboard_update carried the new vote totals of the pubkeys in a block
+//   2: leaderboard_update carries top-N rank changes; per-pubkey totals moved to pubkey_votes
+pub const EVENT_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    // A new or changed block was stored; the payload carries the block
+    BlockIngested,
+    // Rows of the leaderboard top that changed because of the block in blockId
+    LeaderboardUpdate,
+    // The whole leaderboard top, sent to a client when it subscribes to the leaderboard. Its seq
+    // is that of the latest event, so later leaderboard_update events apply on top of it.
+    LeaderboardSnapshot,
+    // New vote totals of every pubkey that voted in the block in blockId
+    PubkeyVotes,
+    // The block in blockId has entries with more than one final hash
+    ForkDetected,
+    // A block could not be fetched or stored
+    IngestError,
+    // Sent periodically so clients can detect dead connections; seq is the latest published one
+    Heartbeat,
+    // Sent to a single client that fell behind; seq is the last event it received before the gap
+    Lagged,
+    // Reply to a subscribe or unsubscribe command with the client's subscriptions. Replies to
+    // commands go to a single client and carry the seq of the last event it received.
+    Subscriptions,
+    // Reply to a resume that could not be served; a leaderboard snapshot follows
+    ResumeFailed,
+    // Reply to a command that could not be understood
+    Error,
+}
+
+// What to do with a WebSocket client that fell behind the broadcast channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LagPolicy {
+    // Tell it how many events it missed, send a fresh leaderboard snapshot and carry on
+    Resync,
+    // Tell it how many events it missed and close the connection
+    Disconnect,
+}
+
+impl LagPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "resync" => Some(LagPolicy::Resync),
+            "disconnect" => Some(LagPolicy::Disconnect),
+            _ => None,
+        }
+    }
+}
+
+// Envelope of every message sent to WebSocket clients
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub v: u32,
+    #[serde(rename = "type")]
+    pub kind: EventKind,
+    pub seq: u64,
+    #[serde(rename = "blockId")]
+    pub block_id: Option<u32>,
+    #[serde(serialize_with = "mongodb::bson::serde_helpers::serialize_bson_datetime_as_rfc3339_string")]
+    pub ts: DateTime,
+    pub payload: serde_json::Value,
+}
+
+impl Event {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ResumeError {
+    #[error("Cannot replay {0} events, reload the current state over HTTP instead")]
+    TooFarBehind(u64),
+    #[error("Events after seq {0} are no longer available")]
+    Expired(u64),
+    #[error("No events recorded for block ID {0}")]
+    UnknownBlock(u32),
+    #[error("Seq {0} has not been published yet")]
+    Ahead(u64),
+    #[error("Failed to read the event log: {0}")]
+    Log(#[from] mongodb::error::Error),
+}
+
+// Where a reconnecting client wants to continue
+#[derive(Debug, Clone, Copy)]
+pub enum ResumePoint {
+    // After the event with this sequence number
+    Seq(u64),
+    // After the last event about this block
+    BlockId(u32),
+}
+
+// Sequence numbers reserved in the stored counter at a time. A new reservation is made once
+// fewer than half of the current one are left.
+const SEQ_RESERVATION: u64 = 1000;
+
+// Numbers events and fans them out to every subscriber
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<Arc<Event>>,
+    // Held while sending so events reach the channel in sequence order
+    state: Arc<Mutex<BusState>>,
+    // Latest sequence number, for the task extending the stored reservation
+    reserve_tx: watch::Sender<u64>,
+    // Durable copy of every numbered event, for clients that fell out of the ring buffer
+    log: Collection<Document>,
+    log_tx: mpsc::Sender<Arc<Event>>,
+    max_replay: u64,
+    metrics: Arc<Metrics>,
+}
+
+struct BusState {
+    // Sequence number of the latest published event, 0 before the first one
+    seq: u64,
+    // Highest sequence number reserved in the stored counter; numbers up to it are never handed
+    // out again, also after a crash
+    reserved: u64,
+    // Leaderboard top as of the latest leaderboard_update
+    top: Vec<LeaderboardRow>,
+    // The most recent numbered events, oldest first
+    recent: VecDeque<Arc<Event>>,
+    buffer_size: usize,
+}
+
+impl EventBus {
+    // Continue numbering after everything the previous run reserved, so no number is reused even
+    // if that run crashed, and start writing new events to the log. The unused rest of the old
+    // reservation is skipped; clients resuming across it are told their events expired.
+    pub async fn start(
+        settings: &EventsConfig,
+        log: Collection<Document>,
+        counter: Collection<Document>,
+        metrics: Arc<Metrics>,
+    ) -> Result<Self, mongodb::error::Error> {
+        let seq = event_log::init_seq(&counter, &log).await?;
+        let reserved = seq + SEQ_RESERVATION;
+        event_log::reserve_seq(&counter, reserved).await?;
+        let (log_tx, log_rx) = mpsc::channel(settings.log_queue_capacity);
+        tokio::spawn(event_log::run_event_log(log.clone(), log_rx, metrics.clone()));
+
+        let (tx, _rx) = broadcast::channel(settings.channel_capacity);
+        let state = BusState { seq, reserved, top: Vec::new(), recent: VecDeque::new(), buffer_size: settings.buffer_size };
+        let state = Arc::new(Mutex::new(state));
+        let (reserve_tx, reserve_rx) = watch::channel(seq);
+        tokio::spawn(run_seq_reservation(counter, state.clone(), reserve_rx));
+        Ok(EventBus { tx, state, reserve_tx, log, log_tx, max_replay: settings.max_replay, metrics })
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Event>> {
+        self.tx.subscribe()
+    }
+
+    // Numbered events after `point`, from the ring buffer when it reaches back far enough and
+    // from the event log otherwise
+    pub async fn replay(&self, point: ResumePoint) -> Result<Vec<Arc<Event>>, ResumeError> {
+        let from = match point {
+            ResumePoint::Seq(seq) => seq,
+            ResumePoint::BlockId(block_id) => match self.recent_seq_for_block(block_id) {
+                Some(seq) => seq,
+                None => event_log::last_seq_for_block(&self.log, block_id).await?.ok_or(ResumeError::UnknownBlock(block_id))?,
+            },
+        };
+
+        let (latest, buffered) = {
+            let state = self.state.lock().unwrap();
+            let buffered: Vec<Arc<Event>> = state.recent.iter().filter(|event| event.seq > from).cloned().collect();
+            (state.seq, buffered)
+        };
+        let logged = match plan_replay(from, latest, buffered.len(), self.max_replay)? {
+            ReplayPlan::Buffered => return Ok(buffered),
+            ReplayPlan::FromLog(limit) => event_log::events_after(&self.log, from, limit as usize).await?,
+        };
+        merge_replay(from, latest, logged, buffered)
+    }
+
+    fn recent_seq_for_block(&self, block_id: u32) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        state.recent.iter().rev().find(|event| event.block_id == Some(block_id)).map(|event| event.seq)
+    }
+
+    // Publish an event under the next sequence number. Nobody listening is not an error.
+    pub fn publish(&self, kind: EventKind, block_id: Option<u32>, payload: serde_json::Value) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        self.send(&mut state, kind, block_id, payload)
+    }
+
+    // Remember the leaderboard top without announcing it, e.g. when ingestion starts
+    pub fn set_top(&self, top: Vec<LeaderboardRow>) {
+        self.state.lock().unwrap().top = top;
+    }
+
+    // Publish the rows of the leaderboard top that differ from the previous top, if any
+    pub fn publish_top(&self, block_id: u32, top: Vec<LeaderboardRow>) {
+        let mut state = self.state.lock().unwrap();
+        let changes = leaderboard::top_changes(&state.top, &top);
+        if changes.is_empty() {
+            return;
+        }
+        self.send(&mut state, EventKind::LeaderboardUpdate, Some(block_id), serde_json::json!({ "changes": changes }));
+        state.top = top;
+    }
+
+    // The current leaderboard top for a client that just subscribed to it
+    pub fn leaderboard_snapshot(&self) -> Event {
+        let state = self.state.lock().unwrap();
+        Event {
+            v: EVENT_VERSION,
+            kind: EventKind::LeaderboardSnapshot,
+            seq: state.seq,
+            block_id: None,
+            ts: DateTime::now(),
+            payload: serde_json::json!({ "top": state.top }),
+        }
+    }
+
+    // Heartbeats repeat the latest sequence number instead of taking a new one
+    pub fn heartbeat(&self) {
+        let state = self.state.lock().unwrap();
+        let event = Event {
+            v: EVENT_VERSION,
+            kind: EventKind::Heartbeat,
+            seq: state.seq,
+            block_id: None,
+            ts: DateTime::now(),
+            payload: serde_json::json!({}),
+        };
+        let _ = self.tx.send(Arc::new(event));
+    }
+
+    // Tells one client how many events it missed; seq is the last one it received, so a
+    // reconnect with resume_from picks up from there
+    pub fn lagged(&self, last_delivered: u64, skipped: u64, policy: LagPolicy) -> Event {
+        Event {
+            v: EVENT_VERSION,
+            kind: EventKind::Lagged,
+            seq: last_delivered,
+            block_id: None,
+            ts: DateTime::now(),
+            payload: serde_json::json!({ "skipped": skipped, "policy": policy }),
+        }
+    }
+
+    fn send(&self, state: &mut BusState, kind: EventKind, block_id: Option<u32>, payload: serde_json::Value) -> u64 {
+        state.seq += 1;
+        let seq = state.seq;
+        let event = Arc::new(Event { v: EVENT_VERSION, kind, seq, block_id, ts: DateTime::now(), payload });
+
+        // Events are always sent, also while the counter cannot be written; they are only counted
+        // so a crash in that state, which could reuse their numbers, does not go unnoticed
+        if seq > state.reserved {
+            Metrics::increment(&self.metrics.events_unreserved, 1);
+        }
+        if state.reserved.saturating_sub(seq) < SEQ_RESERVATION / 2 {
+            self.reserve_tx.send_replace(seq);
+        }
+
+        state.recent.push_back(event.clone());
+        while state.recent.len() > state.buffer_size {
+            state.recent.pop_front();
+        }
+        // The log falling behind must not hold up ingestion; replays report the hole it leaves
+        if self.log_tx.try_send(event.clone()).is_err() {
+            Metrics::increment(&self.metrics.event_log_dropped, 1);
+        }
+        let _ = self.tx.send(event);
+        Metrics::increment(&self.metrics.events_published, 1);
+        seq
+    }
+}
+
+// Where the events after `from` come from when the ring buffer holds `buffered` of them
+#[derive(Debug, PartialEq)]
+enum ReplayPlan {
+    // The buffer holds every event up to the latest
+    Buffered,
+    // Read up to this many events from the log
+    FromLog(u64),
+}
+
+fn plan_replay(from: u64, latest: u64, buffered: usize, max_replay: u64) -> Result<ReplayPlan, ResumeError> {
+    if from > latest {
+        return Err(ResumeError::Ahead(from));
+    }
+    let missing = latest - from;
+    if missing > max_replay {
+        return Err(ResumeError::TooFarBehind(missing));
+    }
+    if buffered as u64 == missing {
+        Ok(ReplayPlan::Buffered)
+    } else {
+        Ok(ReplayPlan::FromLog(missing))
+    }
+}
+
+// Combine logged events with those still buffered, which also covers events on their way into
+// the log. Every event from `from` + 1 to `latest` must be present; a write dropped from the log
+// and already evicted from the buffer leaves a hole that replay must not skip over.
+fn merge_replay(from: u64, latest: u64, logged: Vec<Arc<Event>>, buffered: Vec<Arc<Event>>) -> Result<Vec<Arc<Event>>, ResumeError> {
+    let mut events: Vec<Arc<Event>> = buffered.into_iter().chain(logged).filter(|event| event.seq > from).collect();
+    // Stable, so a buffered event wins over its logged copy
+    events.sort_by_key(|event| event.seq);
+    events.dedup_by_key(|event| event.seq);
+
+    let mut expected = from + 1;
+    for event in &events {
+        if event.seq != expected {
+            return Err(ResumeError::Expired(expected - 1));
+        }
+        expected += 1;
+    }
+    if expected <= latest {
+        return Err(ResumeError::Expired(expected - 1));
+    }
+    Ok(events)
+}
+
+// Keep the stored reservation ahead of the latest sequence number, retrying while the counter
+// cannot be written
+async fn run_seq_reservation(counter: Collection<Document>, state: Arc<Mutex<BusState>>, mut rx: watch::Receiver<u64>) {
+    while rx.changed().await.is_ok() {
+        loop {
+            let reserved = *rx.borrow_and_update() + SEQ_RESERVATION;
+            match event_log::reserve_seq(&counter, reserved).await {
+                Ok(()) => {
+                    let mut state = state.lock().unwrap();
+                    state.reserved = state.reserved.max(reserved);
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("Error reserving event sequence numbers: {}", e);
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(seqs: std::ops::RangeInclusive<u64>) -> Vec<Arc<Event>> {
+        seqs.map(|seq| {
+            Arc::new(Event {
+                v: EVENT_VERSION,
+                kind: EventKind::BlockIngested,
+                seq,
+                block_id: Some(seq as u32),
+                ts: DateTime::now(),
+                payload: serde_json::Value::Null,
+            })
+        })
+        .collect()
+    }
+
+    fn seqs(events: &[Arc<Event>]) -> Vec<u64> {
+        events.iter().map(|event| event.seq).collect()
+    }
+
+    #[test]
+    fn fully_buffered_resume_skips_the_log() {
+        assert_eq!(plan_replay(5, 10, 5, 100).unwrap(), ReplayPlan::Buffered);
+        assert_eq!(plan_replay(10, 10, 0, 100).unwrap(), ReplayPlan::Buffered);
+    }
+
+    #[test]
+    fn partly_evicted_resume_reads_the_log_and_the_buffer() {
+        assert_eq!(plan_replay(2, 10, 4, 100).unwrap(), ReplayPlan::FromLog(8));
+
+        // The log has not caught up with the last buffered events yet
+        let replayed = merge_replay(2, 10, events(3..=8), events(7..=10)).unwrap();
+        assert_eq!(seqs(&replayed), (3..=10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn hole_from_a_dropped_log_write_expires_the_resume() {
+        let mut logged = events(3..=6);
+        logged.extend(events(8..=8));
+        assert!(matches!(merge_replay(2, 12, logged, events(9..=12)), Err(ResumeError::Expired(6))));
+
+        // A hole the buffer still covers is filled from it
+        let mut logged = events(3..=6);
+        logged.extend(events(8..=8));
+        let replayed = merge_replay(2, 12, logged, events(7..=12)).unwrap();
+        assert_eq!(seqs(&replayed), (3..=12).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn expired_log_start_or_missing_tail_expires_the_resume() {
+        assert!(matches!(merge_replay(2, 10, events(5..=10), Vec::new()), Err(ResumeError::Expired(2))));
+        assert!(matches!(merge_replay(2, 10, events(3..=8), Vec::new()), Err(ResumeError::Expired(8))));
+    }
+
+    #[test]
+    fn resume_beyond_the_latest_seq_is_rejected() {
+        assert!(matches!(plan_replay(11, 10, 0, 100), Err(ResumeError::Ahead(11))));
+    }
+
+    #[test]
+    fn resume_too_far_behind_is_rejected() {
+        assert!(matches!(plan_replay(0, 101, 10, 100), Err(ResumeError::TooFarBehind(101))));
+    }
+}
@@ -0,0 +1,60 @@
+use std::io::Write;
+use futures_util::StreamExt;
+use mongodb::{Collection, bson::{doc, Document}};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::leaderboard;
+use crate::models::Block;
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("MongoDB error: {0}")]
+    Mongo(#[from] mongodb::error::Error),
+    #[error("Failed to write export: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to serialize export: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+// Write one block per line, in block ID order
+pub async fn export_blocks(
+    collection: &Collection<Document>,
+    filter: Document,
+    out: &mut impl Write,
+) -> Result<usize, ExportError> {
+    let options = mongodb::options::FindOptions::builder().sort(doc! { "blockId": 1 }).build();
+    let mut cursor = collection.find(filter, options).await?;
+    let mut written = 0;
+    while let Some(document) = cursor.next().await {
+        let block: Block = mongodb::bson::from_document(document?).map_err(mongodb::error::Error::from)?;
+        serde_json::to_writer(&mut *out, &block)?;
+        writeln!(out)?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+// Write one ranked leaderboard row per line
+pub async fn export_leaderboard(
+    collection: &Collection<Document>,
+    filter: Document,
+    out: &mut impl Write,
+) -> Result<usize, ExportError> {
+    let tallies = leaderboard::tally_pubkeys(collection, filter).await?;
+    let ranked = leaderboard::rank_tallies(tallies);
+    for (index, (pubkey, tally)) in ranked.iter().enumerate() {
+        let row = json!({
+            "rank": index + 1,
+            "pubkey": pubkey,
+            "votes": tally.counts.votes,
+            "agreed": tally.counts.agreed,
+            "dissented": tally.counts.dissented,
+            "firstBlockId": tally.first_block_id,
+            "lastBlockId": tally.last_block_id,
+        });
+        serde_json::to_writer(&mut *out, &row)?;
+        writeln!(out)?;
+    }
+    Ok(ranked.len())
+}
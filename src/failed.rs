@@ -0,0 +1,221 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use futures_util::StreamExt;
+use mongodb::{Collection, bson::{doc, Document, DateTime}};
+use serde::{Deserialize, Serialize};
+
+use crate::config::RetryConfig;
+use crate::db::Collections;
+use crate::ingest::{self, IngestError};
+use crate::retry;
+use crate::source::{BlockFetch, BlockSource};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FailedBlock {
+    #[serde(rename = "_id")]
+    pub block_id: i32,
+    pub error: String,
+    // Permanent failures are only retried when explicitly asked for
+    pub permanent: bool,
+    pub attempts: u32,
+    #[serde(rename = "firstFailedAt")]
+    pub first_failed_at: DateTime,
+    #[serde(rename = "lastFailedAt")]
+    pub last_failed_at: DateTime,
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+pub struct RetryReport {
+    pub retried: usize,
+    pub recovered: Vec<i32>,
+    #[serde(rename = "stillFailing")]
+    pub still_failing: Vec<i32>,
+    // Being ingested by another task; their failure records stay for the next retry
+    #[serde(rename = "inProgress")]
+    pub in_progress: Vec<i32>,
+}
+
+// Record (or update) a failed block so it can be retried later
+pub async fn record_failure(
+    failed: &Collection<Document>,
+    block_id: i32,
+    error: &str,
+    permanent: bool,
+) -> Result<(), mongodb::error::Error> {
+    let now = DateTime::now();
+    let update = doc! {
+        "$set": { "error": error, "permanent": permanent, "lastFailedAt": now },
+        "$inc": { "attempts": 1 },
+        "$setOnInsert": { "firstFailedAt": now },
+    };
+    let options = mongodb::options::UpdateOptions::builder().upsert(true).build();
+    failed.update_one(doc! { "_id": block_id }, update, options).await?;
+    Ok(())
+}
+
+pub async fn clear_failure(failed: &Collection<Document>, block_id: i32) -> Result<(), mongodb::error::Error> {
+    failed.delete_one(doc! { "_id": block_id }, None).await?;
+    Ok(())
+}
+
+pub async fn list_failures(
+    failed: &Collection<Document>,
+    include_permanent: bool,
+    limit: Option<usize>,
+) -> Result<Vec<FailedBlock>, mongodb::error::Error> {
+    let filter = if include_permanent { doc! {} } else { doc! { "permanent": false } };
+    let options = mongodb::options::FindOptions::builder()
+        .sort(doc! { "_id": 1 })
+        .limit(limit.map(|limit| limit as i64))
+        .build();
+    let mut cursor = failed.find(filter, options).await?;
+    let mut failures = Vec::new();
+    while let Some(document) = cursor.next().await {
+        failures.push(mongodb::bson::from_document(document?)?);
+    }
+    Ok(failures)
+}
+
+// Fetch the recorded failures again, lowest block ID first and at most `limit` of them, clearing
+// the ones that succeed
+pub async fn retry_failures(
+    collections: &Collections,
+    source: &dyn BlockSource,
+    settings: &RetryConfig,
+    include_permanent: bool,
+    limit: Option<usize>,
+) -> Result<RetryReport, mongodb::error::Error> {
+    let failed = &collections.failed;
+    let mut report = RetryReport::default();
+    for failure in list_failures(failed, include_permanent, limit).await? {
+        let block_id = failure.block_id;
+        report.retried += 1;
+        match retry::fetch_with_retry(source, block_id, settings).await {
+            Ok(BlockFetch::Found(block)) => match ingest::ingest_block(collections, &block).await {
+                Ok(_) => {
+                    clear_failure(failed, block_id).await?;
+                    report.recovered.push(block_id);
+                }
+                Err(e @ IngestError::InProgress(_)) => {
+                    println!("{}, retrying it later.", e);
+                    report.in_progress.push(block_id);
+                }
+                Err(e) => {
+                    record_failure(failed, block_id, &format!("Error saving block: {}", e), false).await?;
+                    report.still_failing.push(block_id);
+                }
+            },
+            Ok(BlockFetch::Missing) => {
+                record_failure(failed, block_id, "Block is not available upstream", true).await?;
+                report.still_failing.push(block_id);
+            }
+            Err(e) => {
+                record_failure(failed, block_id, &e.to_string(), !e.is_retryable()).await?;
+                report.still_failing.push(block_id);
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+pub struct RetryJobStatus {
+    pub running: bool,
+    #[serde(rename = "lastReport")]
+    pub last_report: Option<RetryReport>,
+    #[serde(rename = "lastError")]
+    pub last_error: Option<String>,
+}
+
+// A retry of the failed blocks queue started over the admin API. It runs in the background since
+// fetching every failure again with backoff can take far longer than an HTTP request should.
+#[derive(Default)]
+pub struct RetryJob {
+    running: AtomicBool,
+    status: Mutex<RetryJobStatus>,
+}
+
+impl RetryJob {
+    // Start a retry unless one is already running; returns whether it was started
+    pub fn start(
+        self: &Arc<Self>,
+        collections: Collections,
+        source: Arc<dyn BlockSource>,
+        settings: RetryConfig,
+        include_permanent: bool,
+    ) -> bool {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        let job = self.clone();
+        tokio::spawn(async move {
+            let _running = RunningGuard(&job.running);
+            let result = retry_failures(&collections, source.as_ref(), &settings, include_permanent, None).await;
+            let mut status = job.status.lock().unwrap();
+            match result {
+                Ok(report) => {
+                    println!("Retried {} failed blocks, {} recovered.", report.retried, report.recovered.len());
+                    status.last_report = Some(report);
+                    status.last_error = None;
+                }
+                Err(e) => {
+                    eprintln!("Error retrying failed blocks: {}", e);
+                    status.last_error = Some(e.to_string());
+                }
+            }
+        });
+        true
+    }
+
+    pub fn status(&self) -> RetryJobStatus {
+        let status = self.status.lock().unwrap();
+        RetryJobStatus { running: self.running.load(Ordering::SeqCst), ..status.clone() }
+    }
+}
+
+// Clears the running flag when the retry task ends, even if it panics, so later retries are not
+// rejected forever
+struct RunningGuard<'a>(&'a AtomicBool);
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+// Queue a block for the next retry without touching an existing failure record
+pub async fn enqueue_block(
+    failed: &Collection<Document>,
+    block_id: i32,
+    reason: &str,
+) -> Result<bool, mongodb::error::Error> {
+    let now = DateTime::now();
+    let update = doc! {
+        "$setOnInsert": {
+            "error": reason,
+            "permanent": false,
+            "attempts": 0,
+            "firstFailedAt": now,
+            "lastFailedAt": now,
+        },
+    };
+    let options = mongodb::options::UpdateOptions::builder().upsert(true).build();
+    let result = failed.update_one(doc! { "_id": block_id }, update, options).await?;
+    Ok(result.upserted_id.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn running_flag_is_cleared_when_the_retry_panics() {
+        let running = AtomicBool::new(true);
+        let result = std::panic::catch_unwind(|| {
+            let _running = RunningGuard(&running);
+            panic!("retry task failed");
+        });
+        assert!(result.is_err());
+        assert!(!running.load(Ordering::SeqCst));
+    }
+}
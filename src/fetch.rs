@@ -1,65 +1,376 @@
-use std::thread::sleep;
+use std::sync::Arc;
 use std::time::Duration;
-use reqwest;
-use tokio::sync::broadcast;
+use tokio::time::sleep;
 use mongodb::{Collection, bson::Document};
-use serde_json::Value;
+use serde::Deserialize;
+use thiserror::Error;
 
-use crate::models;
+use crate::api::FetchError;
+use crate::config::{Config, FetchConfig};
+use crate::cursor::{self, IngestCursor};
+use crate::db::Collections;
+use crate::events::{EventBus, EventKind};
+use crate::failed;
+use crate::forks;
+use crate::ingest::{self, IngestError, SaveOutcome};
+use crate::leaderboard;
+use crate::retry;
+use crate::snapshots;
+use crate::models::Block;
+use crate::source::{BlockFetch, BlockSource};
 
-const START_BLOCK_ID: i32 = 27961401;
-const END_BLOCK_ID: i32 = 27965401;
-const BLOCK_INCREMENT: i32 = 100;
+// How many block IDs past a missing one to probe before deciding we are at the tip
+const TIP_LOOKAHEAD: i32 = 5;
+// Polls that find the next block missing before probing for later ones, so following the tip
+// does not cost TIP_LOOKAHEAD extra fetches on every poll
+const TIP_PROBE_AFTER_MISSES: u32 = 6;
 
-pub async fn fetch_data_and_broadcast(tx: broadcast::Sender<String>, collection: Collection<Document>) {
-    let client = reqwest::Client::new();
-    let mut block_id = START_BLOCK_ID; // Starting block ID
+// Why the loop could not work out which block to fetch next
+#[derive(Debug, Error)]
+enum ResumeError {
+    #[error("Error loading ingestion cursor: {0}")]
+    Cursor(#[from] mongodb::error::Error),
+    #[error("Error discovering the latest upstream block: {0}")]
+    Tip(#[from] FetchError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FetchMode {
+    // Cycle forever between the configured start and end block IDs
+    Window,
+    // Start at the latest upstream block and advance monotonically as new ones appear
+    Follow,
+}
+
+impl FetchMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "window" => Some(FetchMode::Window),
+            "follow" => Some(FetchMode::Follow),
+            _ => None,
+        }
+    }
+}
+
+impl FetchConfig {
+    // Last block ID of the range the configured mode works on, if it has one
+    pub fn range_end(&self) -> Option<i32> {
+        match self.mode {
+            FetchMode::Window => Some(self.end_block_id),
+            FetchMode::Follow => None,
+        }
+    }
+
+    // Whether `block_id` falls on the step sequence starting at start_block_id
+    pub fn is_on_sequence(&self, block_id: i32) -> bool {
+        block_id >= self.start_block_id && (block_id - self.start_block_id) % self.block_increment == 0
+    }
+
+    // Whether the cursor may be pointed at `block_id`
+    pub fn can_rewind_to(&self, block_id: i32) -> bool {
+        self.is_on_sequence(block_id) && self.range_end().is_none_or(|end| block_id <= end)
+    }
+
+    // Block IDs in [start, end] that fall on the step sequence
+    pub fn block_ids_in_range(&self, start: i32, end: i32) -> Vec<i32> {
+        match self.first_on_sequence(start) {
+            Some(first) if first <= end => (first..=end).step_by(self.block_increment as usize).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // Number of block IDs in [start, end] that fall on the step sequence, without listing them
+    pub fn count_in_range(&self, start: i32, end: i32) -> u64 {
+        match self.first_on_sequence(start) {
+            Some(first) if first <= end => ((end as i64 - first as i64) / self.block_increment as i64) as u64 + 1,
+            _ => 0,
+        }
+    }
+
+    // Lowest block ID on the step sequence at or after `start`, if it fits an i32
+    fn first_on_sequence(&self, start: i32) -> Option<i32> {
+        let increment = self.block_increment as i64;
+        let offset = (start as i64 - self.start_block_id as i64).rem_euclid(increment);
+        let first = if offset == 0 { start as i64 } else { start as i64 + increment - offset };
+        i32::try_from(first).ok()
+    }
+
+    fn next_block_id(&self, block_id: i32) -> i32 {
+        // Increment the block ID
+        let next = block_id + self.block_increment;
+
+        // In window mode, check if block ID exceeds the end limit, reset to start
+        if self.mode == FetchMode::Window && next > self.end_block_id {
+            self.start_block_id
+        } else {
+            next
+        }
+    }
+}
+
+pub async fn fetch_data_and_broadcast(
+    events: EventBus,
+    collections: Collections,
+    source: Arc<dyn BlockSource>,
+    config: Config,
+) {
+    let settings = &config.fetch;
+    let state = &collections.state;
+    // New or changed blocks since the last leaderboard snapshot
+    let mut changed_since_snapshot = 0;
+    // Consecutive polls in follow mode that found the block in `missing_block_id` missing
+    let mut missing_block_id = None;
+    let mut misses = 0;
+
+    // Leaderboard deltas are computed against the top as it stood when ingestion started
+    match leaderboard::page_pubkey_stats(&collections.pubkey_stats, 0, config.events.leaderboard_top_n, true).await {
+        Ok((_, top)) => events.set_top(top),
+        Err(e) => eprintln!("Error reading the leaderboard: {}", e),
+    }
 
     loop {
-        let url = format!("http://xolana.xen.network:4444/fetch_data/{}", block_id);
-
-        match client.get(&url).send().await {
-            Ok(response) => {
-                match response.text().await {
-                    Ok(body) => {
-                        // Broadcast the data
-                        let _ = tx.send(body.clone());
-
-                        // Parse the JSON data
-                        let json_data: Value = serde_json::from_str(&body).unwrap_or_default();
-                        let block: Result<models::Block, _> = serde_json::from_value(json_data);
-
-                        match block {
-                            Ok(block) => {
-                                // Save the data to MongoDB
-                                let doc = block.to_document();
-                                if let Err(e) = save_data_to_mongo(&collection, doc).await {
-                                    eprintln!("Error saving data for block ID {}: {}", block_id, e);
-                                }
-
-                                println!("Broadcasted and saved data for block ID {}.", block_id); // Optional logging
+        // Pick up where the cursor says, so restarts and rewinds resume from the stored position
+        let block_id = match resume_block_id(source.as_ref(), state, &config).await {
+            Ok(block_id) => block_id,
+            Err(e) => {
+                eprintln!("{}", e);
+                sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        let mut ingested = false;
+
+        match retry::fetch_with_retry(source.as_ref(), block_id, &config.retry).await {
+            Ok(BlockFetch::Found(block)) => {
+                // Save the data to MongoDB
+                match ingest::ingest_block(&collections, &block).await {
+                    Ok(outcome) => {
+                        ingested = true;
+
+                        // Announce the block unless we already had this exact one
+                        if outcome != SaveOutcome::Unchanged {
+                            publish_block(&events, &collections, &block, outcome).await;
+                            publish_top(&events, &collections, &config, block.block_id).await;
+                        }
+                        println!("Saved data for block ID {} ({:?}).", block_id, outcome); // Optional logging
+
+                        if outcome != SaveOutcome::Unchanged {
+                            changed_since_snapshot += 1;
+                            if config.snapshots.interval_blocks > 0 && changed_since_snapshot >= config.snapshots.interval_blocks {
+                                changed_since_snapshot = 0;
+                                // Taken before the next block is ingested, so it matches the block it is labelled with
+                                snapshot_leaderboard(&collections, block.block_id).await;
                             }
-                            Err(e) => eprintln!("Error deserializing data for block ID {}: {}", block_id, e),
+                        }
+
+                        if let Err(e) = failed::clear_failure(&collections.failed, block_id).await {
+                            eprintln!("Error clearing failure record for block ID {}: {}", block_id, e);
                         }
                     }
-                    Err(e) => eprintln!("Error reading response body for block ID {}: {}", block_id, e),
+                    // Another task is storing this block; try it again instead of recording a failure
+                    Err(e @ IngestError::InProgress(_)) => {
+                        println!("{}, retrying it later.", e);
+                        sleep(Duration::from_secs(settings.poll_interval_secs)).await;
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("Error saving data for block ID {}: {}", block_id, e);
+                        record_failure(&collections, &events, block_id, &format!("Error saving block: {}", e), false).await;
+                    }
                 }
             }
-            Err(e) => eprintln!("Error fetching data for block ID {}: {}", block_id, e),
+            Ok(BlockFetch::Missing) if settings.mode == FetchMode::Follow => {
+                if missing_block_id != Some(block_id) {
+                    missing_block_id = Some(block_id);
+                    misses = 0;
+                }
+                misses += 1;
+                // Either we caught up with the tip, or upstream skipped this block. Only look
+                // for later blocks once it has stayed missing for a while.
+                let probe = misses >= TIP_PROBE_AFTER_MISSES;
+                if probe {
+                    misses = 0;
+                }
+                if !probe || !has_later_block(source.as_ref(), settings, block_id).await {
+                    sleep(Duration::from_secs(settings.poll_interval_secs)).await;
+                    continue;
+                }
+                eprintln!("Block ID {} is missing upstream but later blocks exist, skipping it.", block_id);
+                record_failure(&collections, &events, block_id, "Block is not available upstream", true).await;
+            }
+            Ok(BlockFetch::Missing) => {
+                eprintln!("Block ID {} is not available upstream.", block_id);
+                record_failure(&collections, &events, block_id, "Block is not available upstream", true).await;
+            }
+            Err(e) => {
+                eprintln!("Error fetching data for block ID {}: {}", block_id, e);
+                record_failure(&collections, &events, block_id, &e.to_string(), !e.is_retryable()).await;
+            }
         }
 
-        // Increment the block ID
-        block_id += BLOCK_INCREMENT;
+        // Persist the next block ID; a false result means the cursor was moved underneath us
+        match cursor::advance_cursor(state, block_id, settings.next_block_id(block_id), ingested).await {
+            Ok(true) => {}
+            Ok(false) => println!("Ingestion cursor changed while fetching block ID {}, resuming from stored cursor.", block_id),
+            Err(e) => eprintln!("Error saving ingestion cursor after block ID {}: {}", block_id, e),
+        }
+
+        sleep(Duration::from_millis(settings.request_interval_ms)).await;
+    }
+}
+
+// Read the stored cursor, starting a fresh one when it is missing or was recorded for a different range
+async fn resume_block_id(
+    source: &dyn BlockSource,
+    state: &Collection<Document>,
+    config: &Config,
+) -> Result<i32, ResumeError> {
+    let settings = &config.fetch;
+    if let Some(cursor) = cursor::load_cursor(state).await? {
+        if cursor.range_start == settings.start_block_id && cursor.range_end == settings.range_end() {
+            // A rewind may have pointed the cursor outside of the window
+            if settings.mode == FetchMode::Window && cursor.next_block_id > settings.end_block_id {
+                return Ok(settings.start_block_id);
+            }
+            return Ok(cursor.next_block_id);
+        }
+        println!(
+            "Ingestion cursor was recorded for range {}-{:?}, starting a new cursor.",
+            cursor.range_start, cursor.range_end
+        );
+    }
+
+    let next_block_id = match settings.mode {
+        FetchMode::Window => settings.start_block_id,
+        FetchMode::Follow => {
+            // Guessing a start on errors would store a cursor far from the tip, so the loop
+            // retries instead
+            let latest = source.latest_block_id().await?.unwrap_or(settings.start_block_id);
+            println!("Following upstream tip starting at block ID {}.", latest);
+            latest
+        }
+    };
+    let cursor = IngestCursor::new(next_block_id, settings.start_block_id, settings.range_end());
+    cursor::store_cursor(state, &cursor).await?;
+    Ok(cursor.next_block_id)
+}
+
+// Remember a block the loop had to skip and tell subscribers; failing to record it is logged but
+// never stops ingestion
+async fn record_failure(collections: &Collections, events: &EventBus, block_id: i32, error: &str, permanent: bool) {
+    let payload = serde_json::json!({ "error": error, "permanent": permanent });
+    events.publish(EventKind::IngestError, u32::try_from(block_id).ok(), payload);
+
+    if let Err(e) = failed::record_failure(&collections.failed, block_id, error, permanent).await {
+        eprintln!("Error recording failure for block ID {}: {}", block_id, e);
+    }
+}
+
+// Publish the stored block, its forks and the new vote totals of everyone who voted in it
+async fn publish_block(events: &EventBus, collections: &Collections, block: &Block, outcome: SaveOutcome) {
+    events.publish(EventKind::BlockIngested, Some(block.block_id), serde_json::json!({ "outcome": outcome, "block": block }));
+
+    let block_forks = forks::block_forks(block);
+    if !block_forks.is_empty() {
+        events.publish(EventKind::ForkDetected, Some(block.block_id), serde_json::json!({ "forks": block_forks }));
+    }
 
-        // Check if block ID exceeds the end limit, reset to start
-        if block_id > END_BLOCK_ID {
-            block_id = START_BLOCK_ID;
+    let counts = leaderboard::block_vote_counts(block);
+    let pubkeys: Vec<&str> = counts.keys().copied().collect();
+    match leaderboard::find_stats_for(&collections.pubkey_stats, &pubkeys).await {
+        Ok(stats) => {
+            let rows: Vec<serde_json::Value> = stats
+                .iter()
+                .map(|stats| serde_json::json!({
+                    "pubkey": stats.pubkey,
+                    "votes": stats.votes,
+                    "agreed": stats.agreed,
+                    "dissented": stats.dissented,
+                    "blockVotes": counts.get(stats.pubkey.as_str()).map_or(0, |counts| counts.votes),
+                }))
+                .collect();
+            events.publish(EventKind::PubkeyVotes, Some(block.block_id), serde_json::json!({ "pubkeys": rows }));
         }
-        sleep(Duration::from_secs(1));
+        Err(e) => eprintln!("Error reading vote totals after block ID {}: {}", block.block_id, e),
     }
 }
 
-async fn save_data_to_mongo(collection: &Collection<Document>, doc: Document) -> Result<(), mongodb::error::Error> {
-    collection.insert_one(doc, None).await?;
-    Ok(())
+// Publish the rows of the leaderboard top that the block changed
+async fn publish_top(events: &EventBus, collections: &Collections, config: &Config, block_id: u32) {
+    match leaderboard::page_pubkey_stats(&collections.pubkey_stats, 0, config.events.leaderboard_top_n, true).await {
+        Ok((_, top)) => events.publish_top(block_id, top),
+        Err(e) => eprintln!("Error reading the leaderboard after block ID {}: {}", block_id, e),
+    }
+}
+
+// Snapshot the leaderboard in the background so a large leaderboard does not stall fetching
+async fn snapshot_leaderboard(collections: &Collections, block_id: u32) {
+    match snapshots::take_snapshot(collections, Some(block_id)).await {
+        Ok(info) => println!("Stored leaderboard snapshot {} with {} pubkeys.", info.id, info.pubkeys),
+        Err(e) => eprintln!("Error storing leaderboard snapshot after block ID {}: {}", block_id, e),
+    }
+}
+
+// Whether any of the next few block IDs after `block_id` are already available upstream
+async fn has_later_block(source: &dyn BlockSource, settings: &FetchConfig, block_id: i32) -> bool {
+    for step in 1..=TIP_LOOKAHEAD {
+        let later_block_id = block_id + step * settings.block_increment;
+        if let Ok(BlockFetch::Found(_)) = source.fetch_block(later_block_id).await {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(start: i32, end: i32, increment: i32) -> FetchConfig {
+        FetchConfig { mode: FetchMode::Window, start_block_id: start, end_block_id: end, block_increment: increment, ..FetchConfig::default() }
+    }
+
+    #[test]
+    fn sequence_starts_at_start_block_id() {
+        let settings = window(1000, 2000, 100);
+        assert!(settings.is_on_sequence(1000));
+        assert!(settings.is_on_sequence(1300));
+        assert!(!settings.is_on_sequence(1350));
+        assert!(!settings.is_on_sequence(900));
+    }
+
+    #[test]
+    fn block_ids_in_range_align_to_the_sequence() {
+        let settings = window(1000, 2000, 100);
+        assert_eq!(settings.block_ids_in_range(1050, 1400), vec![1100, 1200, 1300, 1400]);
+        assert_eq!(settings.block_ids_in_range(1100, 1100), vec![1100]);
+        assert!(settings.block_ids_in_range(1150, 1199).is_empty());
+        // Before the start the step sequence continues backwards
+        assert_eq!(settings.block_ids_in_range(850, 1000), vec![900, 1000]);
+    }
+
+    #[test]
+    fn extreme_ranges_do_not_overflow() {
+        let settings = window(1000, 2000, 100);
+        assert!(settings.block_ids_in_range(i32::MAX - 10, i32::MAX).is_empty());
+        assert_eq!(settings.block_ids_in_range(i32::MIN, i32::MIN + 100), vec![i32::MIN + 48]);
+        assert_eq!(settings.count_in_range(i32::MIN, i32::MAX), 42_949_673);
+        assert_eq!(settings.count_in_range(1050, 1400), 4);
+        assert_eq!(settings.count_in_range(1150, 1199), 0);
+    }
+
+    #[test]
+    fn window_mode_wraps_and_bounds_rewinds() {
+        let settings = window(1000, 1200, 100);
+        assert_eq!(settings.next_block_id(1100), 1200);
+        assert_eq!(settings.next_block_id(1200), 1000);
+        assert!(settings.can_rewind_to(1200));
+        assert!(!settings.can_rewind_to(1300));
+
+        let follow = FetchConfig { mode: FetchMode::Follow, ..settings };
+        assert_eq!(follow.next_block_id(1200), 1300);
+        assert!(follow.can_rewind_to(1300));
+    }
 }
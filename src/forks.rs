@@ -0,0 +1,173 @@
+use futures_util::StreamExt;
+use mongodb::{Collection, options::FindOptions, bson::{doc, Document}};
+use serde::{Deserialize, Serialize};
+
+use crate::db;
+use crate::indexes;
+use crate::models::{Block, FinalHash};
+
+// How many fork documents to write per insert_many
+const FORKS_BATCH_SIZE: usize = 1000;
+
+// An entry whose voters did not agree on a single final hash
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Fork {
+    #[serde(rename = "blockId")]
+    pub block_id: u32,
+    #[serde(rename = "entryBlockId")]
+    pub entry_block_id: String,
+    // The majority hash, or None when the top vote count is shared
+    #[serde(rename = "consensusHash")]
+    pub consensus_hash: Option<String>,
+    #[serde(rename = "totalVotes")]
+    pub total_votes: u32,
+    // Every competing hash with its vote count and voters, most votes first
+    pub hashes: Vec<FinalHash>,
+}
+
+// The forks contained in a single block
+pub fn block_forks(block: &Block) -> Vec<Fork> {
+    block
+        .entries
+        .iter()
+        .filter(|entry| entry.final_hashes.len() > 1)
+        .map(|entry| {
+            let mut hashes = entry.final_hashes.clone();
+            hashes.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.final_hash.cmp(&b.final_hash)));
+            Fork {
+                block_id: block.block_id,
+                entry_block_id: entry.block_id.clone(),
+                consensus_hash: entry.consensus_hash().map(|fh| fh.final_hash.clone()),
+                total_votes: hashes.iter().map(|fh| fh.count).sum(),
+                hashes,
+            }
+        })
+        .collect()
+}
+
+// Replace the stored forks of a block with those of its current content
+pub async fn replace_block_forks(forks: &Collection<Document>, block: &Block) -> Result<(), mongodb::error::Error> {
+    forks.delete_many(doc! { "blockId": block.block_id }, None).await?;
+    let documents = fork_documents(&block_forks(block))?;
+    if !documents.is_empty() {
+        forks.insert_many(documents, None).await?;
+    }
+    Ok(())
+}
+
+// Recompute the forks collection from scratch out of the stored blocks, swapping the result in
+// once it is complete
+pub async fn rebuild_forks(blocks: &Collection<Document>, forks: &Collection<Document>) -> Result<usize, mongodb::error::Error> {
+    let staging = db::staging_collection(forks).await?;
+    indexes::create_indexes(&staging, &indexes::forks_indexes()).await?;
+
+    let mut cursor = blocks.find(doc! {}, None).await?;
+    let mut pending = Vec::new();
+    let mut total = 0;
+    while let Some(document) = cursor.next().await {
+        let block: Block = mongodb::bson::from_document(document?)?;
+        pending.extend(fork_documents(&block_forks(&block))?);
+        if pending.len() >= FORKS_BATCH_SIZE {
+            total += pending.len();
+            staging.insert_many(std::mem::take(&mut pending), None).await?;
+        }
+    }
+    if !pending.is_empty() {
+        total += pending.len();
+        staging.insert_many(pending, None).await?;
+    }
+    db::swap_in(&staging, forks).await?;
+    Ok(total)
+}
+
+// One page of the stored forks matching `filter`, ordered by block ID, with the total match count
+pub async fn list_forks(
+    forks: &Collection<Document>,
+    filter: Document,
+    offset: usize,
+    limit: usize,
+    descending: bool,
+) -> Result<(usize, Vec<Fork>), mongodb::error::Error> {
+    let total = forks.count_documents(filter.clone(), None).await? as usize;
+
+    let direction = if descending { -1 } else { 1 };
+    let options = FindOptions::builder()
+        .sort(doc! { "blockId": direction, "entryBlockId": direction })
+        .skip(offset as u64)
+        .limit(limit as i64)
+        .build();
+    let mut cursor = forks.find(filter, options).await?;
+    let mut items = Vec::new();
+    while let Some(document) = cursor.next().await {
+        items.push(mongodb::bson::from_document(document?)?);
+    }
+    Ok((total, items))
+}
+
+fn fork_documents(forks: &[Fork]) -> Result<Vec<Document>, mongodb::error::Error> {
+    forks
+        .iter()
+        .map(|fork| mongodb::bson::to_document(fork).map_err(mongodb::error::Error::from))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Entry;
+
+    fn final_hash(hash: &str, pubkeys: &[&str]) -> FinalHash {
+        FinalHash {
+            final_hash: hash.to_string(),
+            count: pubkeys.len() as u32,
+            pubkeys: pubkeys.iter().map(|pubkey| pubkey.to_string()).collect(),
+        }
+    }
+
+    fn block(entries: Vec<Vec<FinalHash>>) -> Block {
+        let entries = entries
+            .into_iter()
+            .enumerate()
+            .map(|(i, final_hashes)| Entry { block_id: format!("7-{}", i), final_hashes })
+            .collect();
+        Block { block_id: 7, entries }
+    }
+
+    fn hash_names(fork: &Fork) -> Vec<&str> {
+        fork.hashes.iter().map(|fh| fh.final_hash.as_str()).collect()
+    }
+
+    #[test]
+    fn entries_without_votes_are_no_forks() {
+        assert!(block_forks(&block(vec![Vec::new()])).is_empty());
+    }
+
+    #[test]
+    fn a_single_hash_is_no_fork() {
+        assert!(block_forks(&block(vec![vec![final_hash("a", &["p1", "p2"])]])).is_empty());
+    }
+
+    #[test]
+    fn a_tie_is_a_fork_without_consensus() {
+        let forks = block_forks(&block(vec![vec![final_hash("b", &["p1"]), final_hash("a", &["p2"])]]));
+        assert_eq!(forks.len(), 1);
+        assert_eq!(forks[0].consensus_hash, None);
+        assert_eq!(forks[0].total_votes, 2);
+        // Equal counts are ordered by hash
+        assert_eq!(hash_names(&forks[0]), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn divergent_hashes_are_ordered_by_votes() {
+        let forks = block_forks(&block(vec![
+            vec![final_hash("a", &["p1"])],
+            vec![final_hash("c", &["p1"]), final_hash("d", &["p2", "p3", "p4"]), final_hash("e", &["p5", "p6"])],
+        ]));
+        assert_eq!(forks.len(), 1);
+        let fork = &forks[0];
+        assert_eq!((fork.block_id, fork.entry_block_id.as_str()), (7, "7-1"));
+        assert_eq!(fork.consensus_hash.as_deref(), Some("d"));
+        assert_eq!(fork.total_votes, 6);
+        assert_eq!(hash_names(fork), vec!["d", "e", "c"]);
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use mongodb::{Collection, bson::{doc, Document}};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::config::{Config, FetchConfig};
+use crate::db::Collections;
+use crate::failed;
+use crate::models::bson_block_id;
+use crate::source::BlockSource;
+
+// Most block IDs of the step sequence one scan checks; wider ranges are scanned in parts
+pub const MAX_SCAN_BLOCKS: u64 = 1_000_000;
+
+#[derive(Error, Debug)]
+pub enum GapsError {
+    #[error("MongoDB error: {0}")]
+    Mongo(#[from] mongodb::error::Error),
+    #[error("Range {from}-{to} covers {blocks} block IDs, more than the {max} one scan checks")]
+    TooLarge { from: i32, to: i32, blocks: u64, max: u64 },
+}
+
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct GapRange {
+    pub start: i32,
+    pub end: i32,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GapReport {
+    pub from: i32,
+    pub to: i32,
+    pub expected: usize,
+    pub missing: usize,
+    pub ranges: Vec<GapRange>,
+}
+
+impl GapReport {
+    pub fn missing_block_ids(&self, settings: &FetchConfig) -> Vec<i32> {
+        self.ranges
+            .iter()
+            .flat_map(|range| settings.block_ids_in_range(range.start, range.end))
+            .collect()
+    }
+}
+
+// Highest block ID stored so far, used as the default end of a scan
+pub async fn latest_stored_block_id(blocks: &Collection<Document>) -> Result<Option<i32>, mongodb::error::Error> {
+    stored_block_id(blocks, -1).await
+}
+
+// Lowest block ID stored so far, used as the default start of a scan
+pub async fn earliest_stored_block_id(blocks: &Collection<Document>) -> Result<Option<i32>, mongodb::error::Error> {
+    stored_block_id(blocks, 1).await
+}
+
+async fn stored_block_id(blocks: &Collection<Document>, direction: i32) -> Result<Option<i32>, mongodb::error::Error> {
+    let options = mongodb::options::FindOneOptions::builder()
+        .sort(doc! { "blockId": direction })
+        .projection(doc! { "blockId": 1 })
+        .build();
+    Ok(blocks
+        .find_one(doc! {}, options)
+        .await?
+        .and_then(|document| document.get("blockId").and_then(bson_block_id)))
+}
+
+// Find the block IDs of the step sequence in [from, to] that are not in the blocks collection.
+// Missing bounds default to the earliest and latest stored blocks, so the blocks before the tip
+// that follow mode started at are not reported as missing. Both bounds are clamped to the
+// configured sequence and `to` to the latest stored block, since later blocks are not fetched
+// yet rather than missing.
+pub async fn find_gaps(
+    blocks: &Collection<Document>,
+    settings: &FetchConfig,
+    from: Option<i32>,
+    to: Option<i32>,
+) -> Result<GapReport, GapsError> {
+    let from = match from {
+        Some(from) => from,
+        None => earliest_stored_block_id(blocks).await?.unwrap_or(settings.start_block_id),
+    }
+    .max(settings.start_block_id);
+    let latest = latest_stored_block_id(blocks).await?.unwrap_or(from);
+    let to = to.unwrap_or(latest).min(latest).min(settings.range_end().unwrap_or(i32::MAX));
+
+    let count = settings.count_in_range(from, to);
+    if count > MAX_SCAN_BLOCKS {
+        return Err(GapsError::TooLarge { from, to, blocks: count, max: MAX_SCAN_BLOCKS });
+    }
+    if count == 0 {
+        return Ok(GapReport { from, to, expected: 0, missing: 0, ranges: Vec::new() });
+    }
+
+    let filter = doc! { "blockId": { "$gte": from, "$lte": to } };
+    let present: HashSet<i32> = blocks
+        .distinct("blockId", filter, None)
+        .await?
+        .iter()
+        .filter_map(bson_block_id)
+        .collect();
+
+    let expected = settings.block_ids_in_range(from, to);
+    let ranges = gap_ranges(&expected, &present, settings.block_increment);
+
+    Ok(GapReport {
+        from,
+        to,
+        expected: expected.len(),
+        missing: ranges.iter().map(|range| range.count).sum(),
+        ranges,
+    })
+}
+
+// Merge the expected block IDs missing from `present` into runs of consecutive steps
+fn gap_ranges(expected: &[i32], present: &HashSet<i32>, block_increment: i32) -> Vec<GapRange> {
+    let mut ranges: Vec<GapRange> = Vec::new();
+    for &block_id in expected.iter().filter(|id| !present.contains(id)) {
+        match ranges.last_mut() {
+            Some(range) if range.end + block_increment == block_id => {
+                range.end = block_id;
+                range.count += 1;
+            }
+            _ => ranges.push(GapRange { start: block_id, end: block_id, count: 1 }),
+        }
+    }
+    ranges
+}
+
+// Put the missing blocks of the report on the failed blocks queue, lowest first and at most
+// `limit` of them; returns how many were new
+pub async fn enqueue_gaps(
+    failed: &Collection<Document>,
+    report: &GapReport,
+    settings: &FetchConfig,
+    limit: Option<usize>,
+) -> Result<usize, mongodb::error::Error> {
+    let mut enqueued = 0;
+    for block_id in report.missing_block_ids(settings).into_iter().take(limit.unwrap_or(usize::MAX)) {
+        if failed::enqueue_block(failed, block_id, "Missing from the blocks collection").await? {
+            enqueued += 1;
+        }
+    }
+    Ok(enqueued)
+}
+
+// Periodically scan for gaps, enqueue them and retry the queue, a bounded number of blocks per
+// scan so a large gap does not turn into a backfill running inside serve
+pub async fn run_gap_repair(collections: Collections, source: Arc<dyn BlockSource>, config: Config) {
+    let interval = Duration::from_secs(config.gaps.repair_interval_secs);
+    let limit = config.gaps.max_repair_blocks;
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let report = match find_gaps(&collections.blocks, &config.fetch, None, None).await {
+            Ok(report) => report,
+            Err(e) => {
+                eprintln!("Error scanning for gaps: {}", e);
+                continue;
+            }
+        };
+        if report.missing > limit {
+            println!(
+                "{} blocks are missing between {} and {}, repairing {} per scan; run backfill to fetch them all at once.",
+                report.missing, report.from, report.to, limit
+            );
+        }
+        match enqueue_gaps(&collections.failed, &report, &config.fetch, Some(limit)).await {
+            Ok(0) => {}
+            Ok(enqueued) => println!("Enqueued {} missing blocks for re-fetch.", enqueued),
+            Err(e) => eprintln!("Error enqueueing gaps: {}", e),
+        }
+
+        match failed::retry_failures(&collections, source.as_ref(), &config.retry, false, Some(limit)).await {
+            Ok(retry) if retry.retried > 0 => println!(
+                "Gap repair retried {} blocks, {} recovered.",
+                retry.retried,
+                retry.recovered.len()
+            ),
+            Ok(_) => {}
+            Err(e) => eprintln!("Error retrying failed blocks: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_consecutive_missing_steps() {
+        let expected: Vec<i32> = (0..10).map(|n| 100 + n * 10).collect();
+        let present: HashSet<i32> = [100, 110, 150, 190].into_iter().collect();
+        let ranges: Vec<(i32, i32, usize)> = gap_ranges(&expected, &present, 10)
+            .iter()
+            .map(|range| (range.start, range.end, range.count))
+            .collect();
+        assert_eq!(ranges, vec![(120, 140, 3), (160, 180, 3)]);
+    }
+
+    #[test]
+    fn no_ranges_without_gaps() {
+        let expected = vec![1, 2, 3];
+        let present: HashSet<i32> = expected.iter().copied().collect();
+        assert!(gap_ranges(&expected, &present, 1).is_empty());
+    }
+}
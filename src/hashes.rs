@@ -0,0 +1,45 @@
+use futures_util::StreamExt;
+use mongodb::{Collection, options::FindOptions, bson::{doc, Document}};
+use serde::Serialize;
+
+use crate::models::Block;
+
+// One place a final hash was voted for
+#[derive(Debug, Serialize)]
+pub struct HashOccurrence {
+    #[serde(rename = "blockId")]
+    pub block_id: u32,
+    #[serde(rename = "entryBlockId")]
+    pub entry_block_id: String,
+    pub count: u32,
+    pub pubkeys: Vec<String>,
+    // Whether this hash is the consensus hash of its entry
+    pub consensus: bool,
+}
+
+// Every entry that carries `final_hash`, ordered by block ID. Relies on the
+// entries.finalHashes.finalHash index.
+pub async fn find_final_hash(
+    blocks: &Collection<Document>,
+    final_hash: &str,
+) -> Result<Vec<HashOccurrence>, mongodb::error::Error> {
+    let options = FindOptions::builder().sort(doc! { "blockId": 1 }).build();
+    let mut cursor = blocks.find(doc! { "entries.finalHashes.finalHash": final_hash }, options).await?;
+    let mut occurrences = Vec::new();
+    while let Some(document) = cursor.next().await {
+        let block: Block = mongodb::bson::from_document(document?)?;
+        for entry in &block.entries {
+            let consensus = entry.consensus_hash().map(|fh| fh.final_hash.as_str());
+            for fh in entry.final_hashes.iter().filter(|fh| fh.final_hash == final_hash) {
+                occurrences.push(HashOccurrence {
+                    block_id: block.block_id,
+                    entry_block_id: entry.block_id.clone(),
+                    count: fh.count,
+                    pubkeys: fh.pubkeys.clone(),
+                    consensus: consensus == Some(final_hash),
+                });
+            }
+        }
+    }
+    Ok(occurrences)
+}
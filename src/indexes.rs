@@ -0,0 +1,324 @@
+use std::time::Duration;
+use futures_util::TryStreamExt;
+use mongodb::{Collection, IndexModel, options::IndexOptions, bson::{doc, Bson, Document}};
+use serde::Serialize;
+
+use crate::db::Collections;
+
+// An index some query relies on
+pub struct IndexSpec {
+    pub name: &'static str,
+    pub keys: Document,
+    pub unique: bool,
+    // Makes this a TTL index removing documents this long after the indexed date
+    pub expire_after: Option<Duration>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct IndexDrift {
+    pub collection: String,
+    // Declared but not present
+    pub missing: Vec<String>,
+    // Present with the declared name but different keys or options
+    pub mismatched: Vec<String>,
+    // Present but not declared
+    pub unexpected: Vec<String>,
+}
+
+impl IndexDrift {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty() && self.unexpected.is_empty()
+    }
+}
+
+// Indexes created by earlier versions that no query uses; they are dropped on migration
+const LEGACY_BLOCK_INDEXES: &[&str] = &[
+    // Unique index on the nested entries.blockId strings, superseded by blockId_1
+    "entries.blockId_1",
+    // Per-pubkey index without block ordering, superseded by entries.finalHashes.pubkeys_1_blockId_1
+    "entries.finalHashes.pubkeys_1",
+];
+
+// Keeps one document per block for the upserts keyed on blockId
+const BLOCK_ID_INDEX: &str = "blockId_1";
+
+fn block_indexes() -> Vec<IndexSpec> {
+    vec![
+        // /block/{id}, /blocks/{start}/{end}, upserts and gap scans
+        IndexSpec { name: BLOCK_ID_INDEX, keys: doc! { "blockId": 1 }, unique: true, expire_after: None },
+        // Per-pubkey lookups, also serving their blocks in order for profiles and first/last blocks
+        IndexSpec { name: "entries.finalHashes.pubkeys_1_blockId_1", keys: doc! { "entries.finalHashes.pubkeys": 1, "blockId": 1 }, unique: false, expire_after: None },
+        // /hashes/{finalHash} lookups
+        IndexSpec { name: "entries.finalHashes.finalHash_1", keys: doc! { "entries.finalHashes.finalHash": 1 }, unique: false, expire_after: None },
+    ]
+}
+
+pub fn pubkey_stats_indexes() -> Vec<IndexSpec> {
+    vec![
+        // Global leaderboard ordering
+        IndexSpec { name: "votes_-1__id_1", keys: doc! { "votes": -1, "_id": 1 }, unique: false, expire_after: None },
+    ]
+}
+
+pub fn forks_indexes() -> Vec<IndexSpec> {
+    vec![
+        // /forks range filter and ordering, and per-block replacement at ingest
+        IndexSpec { name: "blockId_1_entryBlockId_1", keys: doc! { "blockId": 1, "entryBlockId": 1 }, unique: false, expire_after: None },
+    ]
+}
+
+pub fn hourly_stats_indexes() -> Vec<IndexSpec> {
+    vec![
+        // One bucket per pubkey and hour, also serving the window range scan
+        IndexSpec { name: "hour_1_pubkey_1", keys: doc! { "hour": 1, "pubkey": 1 }, unique: true, expire_after: None },
+    ]
+}
+
+fn snapshots_indexes() -> Vec<IndexSpec> {
+    vec![
+        // Snapshot listing ordered by time
+        IndexSpec { name: "takenAt_-1", keys: doc! { "takenAt": -1 }, unique: false, expire_after: None },
+    ]
+}
+
+fn snapshot_rows_indexes() -> Vec<IndexSpec> {
+    vec![
+        // Paging through the rows of one snapshot
+        IndexSpec { name: "snapshotId_1_rank_1", keys: doc! { "snapshotId": 1, "rank": 1 }, unique: true, expire_after: None },
+    ]
+}
+
+// How long WebSocket events stay available for resume_from
+const EVENT_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+fn events_indexes() -> Vec<IndexSpec> {
+    vec![
+        // Expire old events
+        IndexSpec { name: "ts_1", keys: doc! { "ts": 1 }, unique: false, expire_after: Some(EVENT_RETENTION) },
+        // resume_from a block ID
+        IndexSpec { name: "blockId_1", keys: doc! { "blockId": 1 }, unique: false, expire_after: None },
+    ]
+}
+
+// Every collection together with the indexes declared for it and the legacy ones to drop
+fn declared_indexes(collections: &Collections) -> Vec<(&Collection<Document>, Vec<IndexSpec>, &'static [&'static str])> {
+    vec![
+        (&collections.blocks, block_indexes(), LEGACY_BLOCK_INDEXES),
+        (&collections.pubkey_stats, pubkey_stats_indexes(), &[]),
+        (&collections.forks, forks_indexes(), &[]),
+        (&collections.hourly_stats, hourly_stats_indexes(), &[]),
+        (&collections.snapshots, snapshots_indexes(), &[]),
+        (&collections.snapshot_rows, snapshot_rows_indexes(), &[]),
+        (&collections.events, events_indexes(), &[]),
+    ]
+}
+
+// Compare the indexes present on each collection with the declared ones
+pub async fn check_indexes(collections: &Collections) -> Result<Vec<IndexDrift>, mongodb::error::Error> {
+    let mut drifts = Vec::new();
+    for (collection, specs, _) in declared_indexes(collections) {
+        drifts.push(collection_drift(collection, &specs).await?);
+    }
+    Ok(drifts)
+}
+
+// Drop mismatched indexes and create the missing ones, then drop legacy indexes. Legacy indexes
+// only go once their replacements exist, so a failed creation never leaves a collection without
+// them. Returns the drift found before migrating.
+pub async fn ensure_indexes(collections: &Collections) -> Result<Vec<IndexDrift>, mongodb::error::Error> {
+    let mut drifts = Vec::new();
+    for (collection, specs, legacy) in declared_indexes(collections) {
+        let drift = collection_drift(collection, &specs).await?;
+
+        // A mismatched index has to go before it can be recreated under the same name
+        for name in &drift.mismatched {
+            println!("Dropping index {} on {}.", name, drift.collection);
+            collection.drop_index(name.as_str(), None).await?;
+        }
+
+        let to_create: Vec<IndexModel> = specs
+            .iter()
+            .filter(|spec| drift.missing.iter().chain(&drift.mismatched).any(|name| name == spec.name))
+            .map(|spec| {
+                println!("Creating index {} on {}.", spec.name, drift.collection);
+                index_model(spec)
+            })
+            .collect();
+        if !to_create.is_empty() {
+            collection.create_indexes(to_create, None).await?;
+        }
+
+        for name in drift.unexpected.iter().filter(|name| legacy.contains(&name.as_str())) {
+            println!("Dropping index {} on {}.", name, drift.collection);
+            collection.drop_index(name.as_str(), None).await?;
+        }
+        drifts.push(drift);
+    }
+    Ok(drifts)
+}
+
+// Whether the unique blockId index that block upserts rely on is in place
+pub async fn has_block_id_index(blocks: &Collection<Document>) -> Result<bool, mongodb::error::Error> {
+    let specs: Vec<IndexSpec> = block_indexes().into_iter().filter(|spec| spec.name == BLOCK_ID_INDEX).collect();
+    let drift = collection_drift(blocks, &specs).await?;
+    Ok(drift.missing.is_empty() && drift.mismatched.is_empty())
+}
+
+// Create the given indexes on a collection that has none yet, such as a rebuild's staging collection
+pub async fn create_indexes(collection: &Collection<Document>, specs: &[IndexSpec]) -> Result<(), mongodb::error::Error> {
+    collection.create_indexes(specs.iter().map(index_model), None).await?;
+    Ok(())
+}
+
+fn index_model(spec: &IndexSpec) -> IndexModel {
+    IndexModel::builder()
+        .keys(spec.keys.clone())
+        .options(
+            IndexOptions::builder()
+                .name(spec.name.to_string())
+                .unique(spec.unique)
+                .expire_after(spec.expire_after)
+                .build(),
+        )
+        .build()
+}
+
+async fn collection_drift(collection: &Collection<Document>, specs: &[IndexSpec]) -> Result<IndexDrift, mongodb::error::Error> {
+    // Listing indexes of a collection that does not exist yet fails with NamespaceNotFound (26)
+    let existing: Vec<IndexModel> = match collection.list_indexes(None).await {
+        Ok(cursor) => cursor.try_collect().await?,
+        Err(e) if matches!(*e.kind, mongodb::error::ErrorKind::Command(ref c) if c.code == 26) => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    Ok(drift_from(collection.name(), &existing, specs))
+}
+
+// Compare the indexes present on a collection with the declared ones
+fn drift_from(collection: &str, existing: &[IndexModel], specs: &[IndexSpec]) -> IndexDrift {
+    let mut drift = IndexDrift { collection: collection.to_string(), ..Default::default() };
+    for spec in specs {
+        match existing.iter().find(|index| index_name(index) == Some(spec.name)) {
+            Some(index) => {
+                if !keys_match(&index.keys, &spec.keys) || !options_match(index.options.as_ref(), spec) {
+                    drift.mismatched.push(spec.name.to_string());
+                }
+            }
+            None => drift.missing.push(spec.name.to_string()),
+        }
+    }
+
+    for index in existing {
+        let Some(name) = index_name(index) else { continue };
+        if name != "_id_" && !specs.iter().any(|spec| spec.name == name) {
+            drift.unexpected.push(name.to_string());
+        }
+    }
+    drift
+}
+
+// Declared indexes are never sparse or partial; either would leave documents out of the index
+fn options_match(options: Option<&IndexOptions>, spec: &IndexSpec) -> bool {
+    let unique = options.and_then(|o| o.unique).unwrap_or(false);
+    let expire_after = options.and_then(|o| o.expire_after);
+    let sparse = options.and_then(|o| o.sparse).unwrap_or(false);
+    let partial = options.is_some_and(|o| o.partial_filter_expression.is_some());
+    unique == spec.unique && expire_after == spec.expire_after && !sparse && !partial
+}
+
+// Key directions may come back as doubles when an index was created from the shell
+fn keys_match(existing: &Document, declared: &Document) -> bool {
+    existing.len() == declared.len()
+        && existing.iter().zip(declared.iter()).all(|((a_key, a_value), (b_key, b_value))| {
+            a_key == b_key && (a_value == b_value || numeric(a_value).zip(numeric(b_value)).is_some_and(|(a, b)| a == b))
+        })
+}
+
+fn numeric(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Int32(v) => Some(*v as f64),
+        Bson::Int64(v) => Some(*v as f64),
+        Bson::Double(v) => Some(*v),
+        _ => None,
+    }
+}
+
+fn index_name(index: &IndexModel) -> Option<&str> {
+    index.options.as_ref().and_then(|o| o.name.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(name: &str, keys: Document, mut options: IndexOptions) -> IndexModel {
+        options.name = Some(name.to_string());
+        IndexModel::builder().keys(keys).options(options).build()
+    }
+
+    fn specs() -> Vec<IndexSpec> {
+        vec![
+            IndexSpec { name: "a_1_b_-1", keys: doc! { "a": 1, "b": -1 }, unique: true, expire_after: None },
+            IndexSpec { name: "ts_1", keys: doc! { "ts": 1 }, unique: false, expire_after: Some(Duration::from_secs(60)) },
+        ]
+    }
+
+    fn unique() -> IndexOptions {
+        IndexOptions::builder().unique(true).build()
+    }
+
+    fn ttl() -> IndexOptions {
+        IndexOptions::builder().expire_after(Duration::from_secs(60)).build()
+    }
+
+    #[test]
+    fn key_order_and_directions_matter() {
+        assert!(keys_match(&doc! { "a": 1, "b": -1 }, &doc! { "a": 1, "b": -1 }));
+        assert!(!keys_match(&doc! { "b": -1, "a": 1 }, &doc! { "a": 1, "b": -1 }));
+        assert!(!keys_match(&doc! { "a": 1, "b": 1 }, &doc! { "a": 1, "b": -1 }));
+        assert!(!keys_match(&doc! { "a": 1 }, &doc! { "a": 1, "b": -1 }));
+        // Shell-created indexes report doubles
+        assert!(keys_match(&doc! { "a": 1.0, "b": -1_i64 }, &doc! { "a": 1, "b": -1 }));
+    }
+
+    #[test]
+    fn matching_indexes_are_clean() {
+        let existing = vec![
+            index("_id_", doc! { "_id": 1 }, IndexOptions::default()),
+            index("a_1_b_-1", doc! { "a": 1, "b": -1 }, unique()),
+            index("ts_1", doc! { "ts": 1 }, ttl()),
+        ];
+        assert!(drift_from("c", &existing, &specs()).is_clean());
+    }
+
+    #[test]
+    fn reordered_keys_are_mismatched() {
+        let existing = vec![index("a_1_b_-1", doc! { "b": -1, "a": 1 }, unique()), index("ts_1", doc! { "ts": 1 }, ttl())];
+        let drift = drift_from("c", &existing, &specs());
+        assert_eq!(drift.mismatched, vec!["a_1_b_-1"]);
+        assert!(drift.missing.is_empty() && drift.unexpected.is_empty());
+    }
+
+    #[test]
+    fn differing_or_extra_options_are_mismatched() {
+        let sparse = IndexOptions::builder().unique(true).sparse(true).build();
+        let partial = IndexOptions::builder().partial_filter_expression(doc! { "ts": { "$exists": true } }).expire_after(Duration::from_secs(60)).build();
+        let existing = vec![index("a_1_b_-1", doc! { "a": 1, "b": -1 }, sparse), index("ts_1", doc! { "ts": 1 }, partial)];
+        assert_eq!(drift_from("c", &existing, &specs()).mismatched, vec!["a_1_b_-1", "ts_1"]);
+
+        let existing = vec![
+            index("a_1_b_-1", doc! { "a": 1, "b": -1 }, IndexOptions::default()),
+            index("ts_1", doc! { "ts": 1 }, IndexOptions::builder().expire_after(Duration::from_secs(30)).build()),
+        ];
+        assert_eq!(drift_from("c", &existing, &specs()).mismatched, vec!["a_1_b_-1", "ts_1"]);
+    }
+
+    #[test]
+    fn missing_and_unexpected_indexes_are_reported() {
+        let existing = vec![index("ts_1", doc! { "ts": 1 }, ttl()), index("old_1", doc! { "old": 1 }, IndexOptions::default())];
+        let drift = drift_from("c", &existing, &specs());
+        assert_eq!(drift.collection, "c");
+        assert_eq!(drift.missing, vec!["a_1_b_-1"]);
+        assert_eq!(drift.unexpected, vec!["old_1"]);
+        assert!(drift.mismatched.is_empty());
+    }
+}
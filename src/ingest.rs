@@ -0,0 +1,236 @@
+use mongodb::{options::{FindOneAndUpdateOptions, ReturnDocument}, bson::{doc, oid::ObjectId, Bson, Document, DateTime}};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::db::Collections;
+use crate::forks;
+use crate::leaderboard::{self, UpdateError};
+use crate::models::Block;
+use crate::windows;
+
+// How long an ingest may take to apply a block's derived updates before another one takes them over
+const CLAIM_TIMEOUT_MS: i64 = 5 * 60 * 1000;
+
+#[derive(Error, Debug)]
+pub enum IngestError {
+    #[error("MongoDB error: {0}")]
+    Mongo(#[from] mongodb::error::Error),
+    #[error("{0}")]
+    Update(#[from] UpdateError),
+    #[error("Block ID {0} is being ingested by another task")]
+    InProgress(u32),
+}
+
+impl From<mongodb::bson::de::Error> for IngestError {
+    fn from(e: mongodb::bson::de::Error) -> Self {
+        IngestError::Mongo(e.into())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SaveOutcome {
+    // The block was not stored yet
+    Inserted,
+    // The block was stored with different content and has been replaced
+    Updated,
+    // The block was stored with identical content; nothing was written
+    Unchanged,
+}
+
+// One update of the derived collections, in the order they are applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Step {
+    RemovePreviousStats,
+    RemovePreviousHourly,
+    AddStats,
+    AddHourly,
+    Forks,
+}
+
+const INSERT_STEPS: &[Step] = &[Step::AddStats, Step::AddHourly, Step::Forks];
+const UPDATE_STEPS: &[Step] = &[Step::RemovePreviousStats, Step::RemovePreviousHourly, Step::AddStats, Step::AddHourly, Step::Forks];
+
+// Derived updates of a stored block that have not been applied yet. They are written together
+// with the block under `pending` and removed one by one as they succeed, so an ingest that fails
+// halfway is finished by the next one instead of leaving the block counted partially or not at all.
+#[derive(Debug, Deserialize)]
+struct Pending {
+    // Identifies these steps in the markers left on the documents they update. Missing on
+    // pending updates written before markers were; they get one when taken over.
+    #[serde(rename = "_id", default)]
+    id: Option<ObjectId>,
+    #[serde(rename = "claimedAt")]
+    claimed_at: DateTime,
+    // The replaced version whose votes are removed
+    previous: Option<Block>,
+    steps: Vec<Step>,
+}
+
+// The stored version of a block whose pending steps one ingest applies
+struct Claim {
+    block_id: u32,
+    content_hash: String,
+    claimed_at: DateTime,
+    pending_id: ObjectId,
+}
+
+impl Claim {
+    // Matches only while the version and the claim are unchanged
+    fn filter(&self) -> Document {
+        doc! { "blockId": self.block_id, "contentHash": &self.content_hash, "pending.claimedAt": self.claimed_at }
+    }
+
+    // Names one step of the claimed updates on the stats documents it touches, the same for
+    // every ingest that applies it
+    fn marker(&self, step: Step) -> String {
+        format!("{}:{:?}", self.pending_id.to_hex(), step)
+    }
+}
+
+// Store a block and keep the derived collections in step with it
+pub async fn ingest_block(collections: &Collections, block: &Block) -> Result<SaveOutcome, IngestError> {
+    let blocks = &collections.blocks;
+    let content_hash = block.content_hash();
+    loop {
+        let now = DateTime::now();
+        let mut document = block.to_document();
+        document.insert("contentHash", &content_hash);
+        document.insert("updatedAt", now);
+        let claim = Claim { block_id: block.block_id, content_hash: content_hash.clone(), claimed_at: now, pending_id: ObjectId::new() };
+
+        // Inserting and reading the stored version is one atomic step, so concurrent ingests of
+        // a new block cannot both count it
+        let mut inserted = document.clone();
+        inserted.insert("changeCount", 0);
+        inserted.insert("observedAt", now);
+        inserted.insert("pending", pending_document(&claim, None, INSERT_STEPS));
+        let options = FindOneAndUpdateOptions::builder().upsert(true).return_document(ReturnDocument::Before).build();
+        let stored = match blocks.find_one_and_update(doc! { "blockId": block.block_id }, doc! { "$setOnInsert": inserted }, options).await {
+            Ok(Some(stored)) => stored,
+            Ok(None) => {
+                apply_steps(collections, &claim, block, None, windows::hour_bucket(now), INSERT_STEPS).await?;
+                return Ok(SaveOutcome::Inserted);
+            }
+            // Two upserts raced to insert the block; the loser reads the winner's version
+            Err(e) if is_duplicate_key(&e) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        // Documents written before hashes were recorded are hashed on the fly
+        let recorded_hash = stored.get_str("contentHash").map(str::to_string).ok();
+        let observed_at = windows::observed_at(&stored).unwrap_or(now);
+        let hour = windows::hour_bucket(observed_at);
+        let pending = match stored.get_document("pending") {
+            Ok(pending) => Some(mongodb::bson::from_document::<Pending>(pending.clone())?),
+            Err(_) => None,
+        };
+        let stored_block: Block = mongodb::bson::from_document(stored)?;
+
+        // The stored version still has steps left from another ingest. Wait for it while it may
+        // still be running, take the steps over once it has timed out, then look again.
+        if let Some(pending) = pending {
+            if now.timestamp_millis() - pending.claimed_at.timestamp_millis() < CLAIM_TIMEOUT_MS {
+                return Err(IngestError::InProgress(block.block_id));
+            }
+            let stale = Claim {
+                block_id: block.block_id,
+                content_hash: recorded_hash.unwrap_or_default(),
+                claimed_at: pending.claimed_at,
+                pending_id: pending.id.unwrap_or_else(ObjectId::new),
+            };
+            let take = doc! { "$set": { "pending.claimedAt": now, "pending._id": stale.pending_id } };
+            let taken = blocks.update_one(stale.filter(), take, None).await?;
+            if taken.matched_count == 1 {
+                let claim = Claim { claimed_at: now, ..stale };
+                apply_steps(collections, &claim, &stored_block, pending.previous.as_ref(), hour, &pending.steps).await?;
+            }
+            continue;
+        }
+
+        let stored_hash = recorded_hash.clone().unwrap_or_else(|| stored_block.content_hash());
+        if stored_hash == content_hash {
+            return Ok(SaveOutcome::Unchanged);
+        }
+
+        // Replace only the version read above; if another ingest replaced it in between, start over
+        document.insert("pending", pending_document(&claim, Some(&stored_block), UPDATE_STEPS));
+        let update = doc! {
+            "$set": document,
+            "$inc": { "changeCount": 1 },
+            // Legacy documents get the time recorded that their votes are bucketed under
+            "$min": { "observedAt": observed_at },
+        };
+        let filter = doc! { "blockId": block.block_id, "contentHash": recorded_hash.map_or(Bson::Null, Bson::String) };
+        if blocks.update_one(filter, update, None).await?.matched_count == 0 {
+            continue;
+        }
+        // Votes stay in the hour the block was first observed, also when its content changes later
+        apply_steps(collections, &claim, block, Some(&stored_block), hour, UPDATE_STEPS).await?;
+        return Ok(SaveOutcome::Updated);
+    }
+}
+
+fn pending_document(claim: &Claim, previous: Option<&Block>, steps: &[Step]) -> Document {
+    let steps = mongodb::bson::to_bson(steps).unwrap_or_default();
+    doc! { "_id": claim.pending_id, "claimedAt": claim.claimed_at, "previous": previous.map(Block::to_document), "steps": steps }
+}
+
+// Apply the claimed steps in order, recording each one as done. Every step updates a document at
+// most once per claim marker, so one that fails partway is safely replayed in full by whoever
+// takes the claim over. Stops with InProgress if the claim was taken over in the meantime.
+async fn apply_steps(
+    collections: &Collections,
+    claim: &Claim,
+    block: &Block,
+    previous: Option<&Block>,
+    hour: DateTime,
+    steps: &[Step],
+) -> Result<(), IngestError> {
+    for (index, &step) in steps.iter().enumerate() {
+        let marker = claim.marker(step);
+        match (step, previous) {
+            (Step::RemovePreviousStats, Some(previous)) => {
+                leaderboard::apply_block_to_stats(&collections.pubkey_stats, previous, -1, &marker).await?;
+                leaderboard::recompute_block_bounds(&collections.blocks, &collections.pubkey_stats, previous).await?;
+            }
+            (Step::RemovePreviousHourly, Some(previous)) => windows::apply_block_to_hourly(&collections.hourly_stats, previous, hour, -1, &marker).await?,
+            (Step::RemovePreviousStats | Step::RemovePreviousHourly, None) => {}
+            (Step::AddStats, _) => leaderboard::apply_block_to_stats(&collections.pubkey_stats, block, 1, &marker).await?,
+            (Step::AddHourly, _) => windows::apply_block_to_hourly(&collections.hourly_stats, block, hour, 1, &marker).await?,
+            (Step::Forks, _) => forks::replace_block_forks(&collections.forks, block).await?,
+        }
+
+        let done = if index + 1 == steps.len() {
+            doc! { "$unset": { "pending": "" } }
+        } else {
+            doc! { "$pull": { "pending.steps": mongodb::bson::to_bson(&step).unwrap_or_default() } }
+        };
+        if collections.blocks.update_one(claim.filter(), done, None).await?.matched_count == 0 {
+            return Err(IngestError::InProgress(claim.block_id));
+        }
+
+        // The step cannot be replayed anymore, so its markers are no longer needed
+        let cleared = match (step, previous) {
+            (Step::RemovePreviousStats, Some(previous)) => leaderboard::clear_stats_marker(&collections.pubkey_stats, previous, &marker).await,
+            (Step::RemovePreviousHourly, Some(previous)) => windows::clear_hourly_marker(&collections.hourly_stats, previous, hour, &marker).await,
+            (Step::AddStats, _) => leaderboard::clear_stats_marker(&collections.pubkey_stats, block, &marker).await,
+            (Step::AddHourly, _) => windows::clear_hourly_marker(&collections.hourly_stats, block, hour, &marker).await,
+            _ => Ok(()),
+        };
+        if let Err(e) = cleared {
+            eprintln!("Error clearing update markers of block ID {}: {}", claim.block_id, e);
+        }
+    }
+    Ok(())
+}
+
+fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    use mongodb::error::{ErrorKind, WriteFailure};
+    match *e.kind {
+        ErrorKind::Command(ref c) => c.code == 11000,
+        ErrorKind::Write(WriteFailure::WriteError(ref w)) => w.code == 11000,
+        _ => false,
+    }
+}
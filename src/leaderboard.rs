@@ -0,0 +1,743 @@
+use std::collections::{HashMap, HashSet};
+use futures_util::StreamExt;
+use mongodb::{Collection, options::FindOneOptions, bson::{doc, Document}};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::db;
+use crate::indexes;
+use crate::models::{self, Block};
+
+// How many stats documents to write per insert_many or update command
+pub const STATS_BATCH_SIZE: usize = 1000;
+
+// A pubkey_stats document
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PubkeyStats {
+    #[serde(rename = "_id")]
+    pub pubkey: String,
+    pub votes: u32,
+    #[serde(rename = "firstBlockId", default)]
+    pub first_block_id: u32,
+    #[serde(rename = "lastBlockId", default)]
+    pub last_block_id: u32,
+    // Votes for the consensus hash of their entry, and for a minority hash
+    #[serde(default)]
+    pub agreed: u32,
+    #[serde(default)]
+    pub dissented: u32,
+}
+
+impl PubkeyStats {
+    pub fn accuracy(&self) -> Option<f64> {
+        accuracy(self.agreed, self.dissented)
+    }
+}
+
+// Share of votes that went to the consensus hash, ignoring entries without one
+pub fn accuracy(agreed: u32, dissented: u32) -> Option<f64> {
+    let decided = agreed + dissented;
+    (decided > 0).then(|| agreed as f64 / decided as f64)
+}
+
+// Votes of one pubkey, split by whether they agreed with consensus
+#[derive(Debug, Serialize, Clone, Copy, Default)]
+pub struct VoteCounts {
+    pub votes: u32,
+    pub agreed: u32,
+    pub dissented: u32,
+}
+
+impl VoteCounts {
+    fn record(&mut self, agreed: Option<bool>) {
+        self.votes += 1;
+        match agreed {
+            Some(true) => self.agreed += 1,
+            Some(false) => self.dissented += 1,
+            None => {}
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct PubkeyTally {
+    #[serde(flatten)]
+    pub counts: VoteCounts,
+    #[serde(rename = "firstBlockId")]
+    pub first_block_id: u32,
+    #[serde(rename = "lastBlockId")]
+    pub last_block_id: u32,
+}
+
+impl PubkeyTally {
+    fn record(&mut self, block_id: u32, agreed: Option<bool>) {
+        self.counts.record(agreed);
+        self.first_block_id = self.first_block_id.min(block_id);
+        self.last_block_id = self.last_block_id.max(block_id);
+    }
+}
+
+// Count every pubkey appearance across the final hashes of a single block
+pub fn block_vote_counts(block: &Block) -> HashMap<&str, VoteCounts> {
+    let mut counts: HashMap<&str, VoteCounts> = HashMap::new();
+    for (pubkey, agreed) in block.entries.iter().flat_map(|entry| entry.votes()) {
+        counts.entry(pubkey).or_default().record(agreed);
+    }
+    counts
+}
+
+// Count every pubkey appearance across the final hashes of the blocks matching `filter`
+pub async fn tally_pubkeys(
+    collection: &Collection<Document>,
+    filter: Document,
+) -> Result<HashMap<String, PubkeyTally>, mongodb::error::Error> {
+    let mut cursor = collection.find(filter, None).await?;
+    let mut tallies: HashMap<String, PubkeyTally> = HashMap::new();
+    while let Some(document) = cursor.next().await {
+        let block: Block = mongodb::bson::from_document(document?)?;
+        for (pubkey, agreed) in block.entries.iter().flat_map(|entry| entry.votes()) {
+            tallies
+                .entry(pubkey.to_string())
+                .or_insert(PubkeyTally { counts: VoteCounts::default(), first_block_id: block.block_id, last_block_id: block.block_id })
+                .record(block.block_id, agreed);
+        }
+    }
+    Ok(tallies)
+}
+
+// A leaderboard position; rank 1 has the most votes
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct LeaderboardRow {
+    pub rank: usize,
+    pub pubkey: String,
+    pub votes: u32,
+}
+
+// A pubkey's leaderboard position together with the rows directly above and below it
+#[derive(Debug, Serialize)]
+pub struct Neighborhood {
+    pub pubkey: String,
+    pub rank: usize,
+    pub votes: u32,
+    // Number of ranked pubkeys on this leaderboard
+    pub total: usize,
+    // Closest to the top first in both lists
+    pub above: Vec<LeaderboardRow>,
+    pub below: Vec<LeaderboardRow>,
+}
+
+impl Neighborhood {
+    // Cut the neighborhood of `pubkey` out of a fully ranked leaderboard
+    pub fn from_ranked(ranked: &[LeaderboardRow], pubkey: &str, around: usize) -> Option<Self> {
+        let position = ranked.iter().position(|row| row.pubkey == pubkey)?;
+        let row = &ranked[position];
+        Some(Neighborhood {
+            pubkey: row.pubkey.clone(),
+            rank: row.rank,
+            votes: row.votes,
+            total: ranked.len(),
+            above: ranked[position.saturating_sub(around)..position].to_vec(),
+            below: ranked[position + 1..(position + 1 + around).min(ranked.len())].to_vec(),
+        })
+    }
+}
+
+// Sort pubkeys by vote count in descending order, ties broken by pubkey
+pub fn rank_tallies(tallies: HashMap<String, PubkeyTally>) -> Vec<(String, PubkeyTally)> {
+    let mut sorted_tallies: Vec<(String, PubkeyTally)> = tallies.into_iter().collect();
+    sorted_tallies.sort_by(|a, b| b.1.counts.votes.cmp(&a.1.counts.votes).then_with(|| a.0.cmp(&b.0)));
+    sorted_tallies
+}
+
+// Same ordering as rank_tallies, as numbered leaderboard rows
+pub fn rank(tallies: HashMap<String, PubkeyTally>) -> Vec<LeaderboardRow> {
+    rank_votes(tallies.into_iter().map(|(pubkey, tally)| (pubkey, tally.counts.votes)).collect())
+}
+
+// Number plain vote counts in leaderboard order
+pub fn rank_votes(votes: HashMap<String, u32>) -> Vec<LeaderboardRow> {
+    let mut sorted: Vec<(String, u32)> = votes.into_iter().collect();
+    sorted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    sorted
+        .into_iter()
+        .enumerate()
+        .map(|(index, (pubkey, votes))| LeaderboardRow { rank: index + 1, pubkey, votes })
+        .collect()
+}
+
+// A row of the leaderboard top that moved; a missing newRank means it dropped out of the top
+#[derive(Debug, Serialize, Clone)]
+pub struct TopChange {
+    pub pubkey: String,
+    pub votes: u32,
+    #[serde(rename = "oldRank")]
+    pub old_rank: Option<usize>,
+    #[serde(rename = "newRank")]
+    pub new_rank: Option<usize>,
+}
+
+// Rows that differ between two versions of the leaderboard top, in new rank order followed by
+// the rows that dropped out
+pub fn top_changes(old: &[LeaderboardRow], new: &[LeaderboardRow]) -> Vec<TopChange> {
+    let old_by_pubkey: HashMap<&str, &LeaderboardRow> = old.iter().map(|row| (row.pubkey.as_str(), row)).collect();
+    let new_pubkeys: HashSet<&str> = new.iter().map(|row| row.pubkey.as_str()).collect();
+
+    let mut changes: Vec<TopChange> = new
+        .iter()
+        .filter(|row| old_by_pubkey.get(row.pubkey.as_str()).is_none_or(|old| old != row))
+        .map(|row| TopChange {
+            pubkey: row.pubkey.clone(),
+            votes: row.votes,
+            old_rank: old_by_pubkey.get(row.pubkey.as_str()).map(|old| old.rank),
+            new_rank: Some(row.rank),
+        })
+        .collect();
+    changes.extend(old.iter().filter(|row| !new_pubkeys.contains(row.pubkey.as_str())).map(|row| TopChange {
+        pubkey: row.pubkey.clone(),
+        votes: row.votes,
+        old_rank: Some(row.rank),
+        new_rank: None,
+    }));
+    changes
+}
+
+// How one pubkey's standing changed between two leaderboards
+#[derive(Debug, Serialize, Clone)]
+pub struct RankChange {
+    pub pubkey: String,
+    #[serde(rename = "votesBefore")]
+    pub votes_before: u32,
+    #[serde(rename = "votesAfter")]
+    pub votes_after: u32,
+    #[serde(rename = "voteDelta")]
+    pub vote_delta: i64,
+    #[serde(rename = "rankBefore")]
+    pub rank_before: Option<usize>,
+    #[serde(rename = "rankAfter")]
+    pub rank_after: Option<usize>,
+    // Positive when the pubkey moved up; None for entrants and dropouts
+    #[serde(rename = "rankDelta")]
+    pub rank_delta: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LeaderboardDiff {
+    // Biggest climb first
+    pub gainers: Vec<RankChange>,
+    // Biggest fall first
+    pub losers: Vec<RankChange>,
+    // Ranked after but not before, best new rank first
+    #[serde(rename = "newEntrants")]
+    pub new_entrants: Vec<RankChange>,
+    // Ranked before but not after, best old rank first
+    pub dropouts: Vec<RankChange>,
+    // Same rank before and after but a different vote count, biggest change first
+    #[serde(rename = "countChanges")]
+    pub count_changes: Vec<RankChange>,
+}
+
+// Compare two ranked leaderboards, keeping at most `limit` rows per list
+pub fn diff_leaderboards(before: &[LeaderboardRow], after: &[LeaderboardRow], limit: usize) -> LeaderboardDiff {
+    let before_by_pubkey: HashMap<&str, &LeaderboardRow> = before.iter().map(|row| (row.pubkey.as_str(), row)).collect();
+    let after_by_pubkey: HashMap<&str, &LeaderboardRow> = after.iter().map(|row| (row.pubkey.as_str(), row)).collect();
+    let change = |pubkey: &str| {
+        let old = before_by_pubkey.get(pubkey);
+        let new = after_by_pubkey.get(pubkey);
+        let votes_before = old.map_or(0, |row| row.votes);
+        let votes_after = new.map_or(0, |row| row.votes);
+        RankChange {
+            pubkey: pubkey.to_string(),
+            votes_before,
+            votes_after,
+            vote_delta: votes_after as i64 - votes_before as i64,
+            rank_before: old.map(|row| row.rank),
+            rank_after: new.map(|row| row.rank),
+            rank_delta: old.zip(new).map(|(old, new)| old.rank as i64 - new.rank as i64),
+        }
+    };
+
+    // Both inputs are in rank order, so entrants and dropouts come out best rank first
+    let new_entrants = after.iter().filter(|row| !before_by_pubkey.contains_key(row.pubkey.as_str()));
+    let dropouts = before.iter().filter(|row| !after_by_pubkey.contains_key(row.pubkey.as_str()));
+
+    let (mut count_changes, mut moved): (Vec<RankChange>, Vec<RankChange>) = after
+        .iter()
+        .filter(|row| before_by_pubkey.contains_key(row.pubkey.as_str()))
+        .map(|row| change(&row.pubkey))
+        .filter(|change| change.rank_delta != Some(0) || change.vote_delta != 0)
+        .partition(|change| change.rank_delta == Some(0));
+    count_changes.sort_by(|a, b| b.vote_delta.abs().cmp(&a.vote_delta.abs()).then_with(|| a.pubkey.cmp(&b.pubkey)));
+    count_changes.truncate(limit);
+    moved.sort_by(|a, b| b.rank_delta.cmp(&a.rank_delta).then_with(|| a.pubkey.cmp(&b.pubkey)));
+    let losers_start = moved.partition_point(|change| change.rank_delta > Some(0));
+    let mut losers = moved.split_off(losers_start);
+    losers.sort_by(|a, b| a.rank_delta.cmp(&b.rank_delta).then_with(|| a.pubkey.cmp(&b.pubkey)));
+    moved.truncate(limit);
+    losers.truncate(limit);
+
+    LeaderboardDiff {
+        gainers: moved,
+        losers,
+        new_entrants: new_entrants.take(limit).map(|row| change(&row.pubkey)).collect(),
+        dropouts: dropouts.take(limit).map(|row| change(&row.pubkey)).collect(),
+        count_changes,
+    }
+}
+
+// Recompute the pubkey_stats collection from scratch out of the stored blocks, swapping the
+// result in once it is complete
+pub async fn rebuild_pubkey_stats(
+    blocks: &Collection<Document>,
+    pubkey_stats: &Collection<Document>,
+) -> Result<usize, mongodb::error::Error> {
+    let tallies = tally_pubkeys(blocks, doc! {}).await?;
+    let total = tallies.len();
+
+    let staging = db::staging_collection(pubkey_stats).await?;
+    indexes::create_indexes(&staging, &indexes::pubkey_stats_indexes()).await?;
+    let documents: Vec<Document> = tallies
+        .into_iter()
+        .map(|(pubkey, tally)| doc! {
+            "_id": pubkey,
+            "votes": tally.counts.votes,
+            "agreed": tally.counts.agreed,
+            "dissented": tally.counts.dissented,
+            "firstBlockId": tally.first_block_id,
+            "lastBlockId": tally.last_block_id,
+        })
+        .collect();
+    for batch in documents.chunks(STATS_BATCH_SIZE) {
+        staging.insert_many(batch, None).await?;
+    }
+    db::swap_in(&staging, pubkey_stats).await?;
+    Ok(total)
+}
+
+#[derive(Error, Debug)]
+pub enum UpdateError {
+    #[error("MongoDB error: {0}")]
+    Mongo(#[from] mongodb::error::Error),
+    #[error("{failed} {collection} updates failed for block ID {block_id}: {first}")]
+    WriteErrors { collection: String, block_id: u32, failed: usize, first: String },
+}
+
+// Add (sign = 1) or remove (sign = -1) a block's votes to pubkey_stats, at most once per
+// `marker`. All upserts are sent as batched update commands instead of one round trip per pubkey.
+pub async fn apply_block_to_stats(
+    pubkey_stats: &Collection<Document>,
+    block: &Block,
+    sign: i32,
+    marker: &str,
+) -> Result<(), UpdateError> {
+    let block_id = block.block_id as i64;
+    let updates: Vec<(Document, Document)> = block_vote_counts(block)
+        .into_iter()
+        .map(|(pubkey, counts)| {
+            let sign = sign as i64;
+            let mut update = doc! {
+                "$inc": {
+                    "votes": sign * counts.votes as i64,
+                    "agreed": sign * counts.agreed as i64,
+                    "dissented": sign * counts.dissented as i64,
+                }
+            };
+            if sign > 0 {
+                update.insert("$min", doc! { "firstBlockId": block_id });
+                update.insert("$max", doc! { "lastBlockId": block_id });
+            }
+            (doc! { "_id": pubkey }, update)
+        })
+        .collect();
+    let initial = (sign > 0).then(|| doc! { "votes": 0, "agreed": 0, "dissented": 0 });
+    send_marked_updates(pubkey_stats, updates, initial, marker, block.block_id).await
+}
+
+// Once a block version's votes are removed, move the first and last block IDs of the pubkeys it
+// was the first or last vote of to the blocks they still vote in, or clear them when there are
+// none. Runs after the new version is stored, so it is counted if it still has the pubkey. A block
+// ingested concurrently for the same pubkey can be missed until the next rebuild-leaderboard.
+pub async fn recompute_block_bounds(
+    blocks: &Collection<Document>,
+    pubkey_stats: &Collection<Document>,
+    removed: &Block,
+) -> Result<(), UpdateError> {
+    let block_id = removed.block_id;
+    let pubkeys: Vec<&str> = block_vote_counts(removed).into_keys().collect();
+    let filter = doc! {
+        "_id": { "$in": &pubkeys },
+        "$or": [{ "firstBlockId": block_id }, { "lastBlockId": block_id }],
+    };
+    let mut cursor = pubkey_stats.find(filter, None).await?;
+    let mut updates = Vec::new();
+    while let Some(document) = cursor.next().await {
+        let stats: PubkeyStats = mongodb::bson::from_document(document?).map_err(mongodb::error::Error::from)?;
+        for (field, order, current) in [("firstBlockId", 1, stats.first_block_id), ("lastBlockId", -1, stats.last_block_id)] {
+            if current != block_id {
+                continue;
+            }
+            // Only while the field still points at the removed block, so a newer $min/$max wins
+            let update = match edge_block_id(blocks, &stats.pubkey, order).await? {
+                Some(edge) => doc! { "$set": { field: edge } },
+                None => doc! { "$unset": { field: "" } },
+            };
+            updates.push(doc! { "q": { "_id": &stats.pubkey, field: block_id }, "u": update });
+        }
+    }
+    send_updates(pubkey_stats, updates, block_id).await
+}
+
+// Lowest (order = 1) or highest (order = -1) ID of the stored blocks `pubkey` votes in
+async fn edge_block_id(blocks: &Collection<Document>, pubkey: &str, order: i32) -> Result<Option<i64>, mongodb::error::Error> {
+    let options = FindOneOptions::builder()
+        .sort(doc! { "blockId": order })
+        .projection(doc! { "blockId": 1 })
+        .build();
+    let document = blocks.find_one(doc! { "entries.finalHashes.pubkeys": pubkey }, options).await?;
+    Ok(document.and_then(|document| document.get("blockId").and_then(models::bson_block_id)).map(i64::from))
+}
+
+// Remove the marker of a finished apply_block_to_stats from the documents it touched
+pub async fn clear_stats_marker(
+    pubkey_stats: &Collection<Document>,
+    block: &Block,
+    marker: &str,
+) -> Result<(), UpdateError> {
+    let targets = block_vote_counts(block).into_keys().map(|pubkey| doc! { "_id": pubkey }).collect();
+    clear_marker(pubkey_stats, targets, marker, block.block_id).await
+}
+
+// Send update statements derived from one block as batched update commands. Write errors are
+// returned after the batch that reported them and earlier batches stay applied, so updates that
+// must not be repeated when a block is replayed go through send_marked_updates instead.
+pub async fn send_updates(
+    collection: &Collection<Document>,
+    updates: Vec<Document>,
+    block_id: u32,
+) -> Result<(), UpdateError> {
+    let namespace = collection.namespace();
+    let db = collection.client().database(&namespace.db);
+    for batch in updates.chunks(STATS_BATCH_SIZE) {
+        let command = doc! { "update": &namespace.coll, "updates": batch, "ordered": false };
+        let response = db.run_command(command, None).await?;
+        if let Ok(errors) = response.get_array("writeErrors") {
+            if !errors.is_empty() {
+                return Err(UpdateError::WriteErrors {
+                    collection: namespace.coll.clone(),
+                    block_id,
+                    failed: errors.len(),
+                    first: format!("{:?}", errors.first()),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+// Apply each (target, update) pair at most once for `marker`, which names one step of one
+// pending block update. The marker is pushed onto the target's `applied` list together with the
+// update, and targets already carrying it are skipped, so a step that failed partway or lost its
+// acknowledgement can be replayed from the start without counting anything twice. With `initial`
+// missing targets are first inserted with those fields, since an upsert guarded by the marker
+// would insert a duplicate of a target that already carries it.
+pub async fn send_marked_updates(
+    collection: &Collection<Document>,
+    updates: Vec<(Document, Document)>,
+    initial: Option<Document>,
+    marker: &str,
+    block_id: u32,
+) -> Result<(), UpdateError> {
+    if let Some(initial) = initial {
+        let inserts = updates
+            .iter()
+            .map(|(target, _)| doc! { "q": target.clone(), "u": { "$setOnInsert": initial.clone() }, "upsert": true })
+            .collect();
+        send_updates(collection, inserts, block_id).await?;
+    }
+    let updates = updates
+        .into_iter()
+        .map(|(mut target, mut update)| {
+            target.insert("applied", doc! { "$ne": marker });
+            update.insert("$push", doc! { "applied": marker });
+            doc! { "q": target, "u": update }
+        })
+        .collect();
+    send_updates(collection, updates, block_id).await
+}
+
+// Drop `marker` from the targets once its step is recorded as done and can no longer be replayed.
+// A marker left behind by a crash in between is never matched again and only takes up space
+// until the next rebuild.
+pub async fn clear_marker(
+    collection: &Collection<Document>,
+    targets: Vec<Document>,
+    marker: &str,
+    block_id: u32,
+) -> Result<(), UpdateError> {
+    let updates = targets
+        .into_iter()
+        .map(|mut target| {
+            target.insert("applied", marker);
+            doc! { "q": target, "u": { "$pull": { "applied": marker } } }
+        })
+        .collect();
+    send_updates(collection, updates, block_id).await
+}
+
+// The stats document of a single pubkey, if it has ever voted
+pub async fn find_pubkey_stats(
+    pubkey_stats: &Collection<Document>,
+    pubkey: &str,
+) -> Result<Option<PubkeyStats>, mongodb::error::Error> {
+    match pubkey_stats.find_one(doc! { "_id": pubkey, "votes": { "$gt": 0 } }, None).await? {
+        Some(document) => Ok(Some(mongodb::bson::from_document(document)?)),
+        None => Ok(None),
+    }
+}
+
+// Stats documents of the given pubkeys, in no particular order
+pub async fn find_stats_for(
+    pubkey_stats: &Collection<Document>,
+    pubkeys: &[&str],
+) -> Result<Vec<PubkeyStats>, mongodb::error::Error> {
+    let mut cursor = pubkey_stats.find(doc! { "_id": { "$in": pubkeys } }, None).await?;
+    let mut stats = Vec::new();
+    while let Some(document) = cursor.next().await {
+        stats.push(mongodb::bson::from_document(document?)?);
+    }
+    Ok(stats)
+}
+
+// Global rank of `stats`: one plus the number of pubkeys with more votes, or as many votes and a
+// smaller pubkey
+pub async fn global_rank(pubkey_stats: &Collection<Document>, stats: &PubkeyStats) -> Result<usize, mongodb::error::Error> {
+    let ahead = doc! {
+        "$or": [
+            { "votes": { "$gt": stats.votes } },
+            { "votes": stats.votes, "_id": { "$lt": &stats.pubkey } },
+        ]
+    };
+    Ok(pubkey_stats.count_documents(ahead, None).await? as usize + 1)
+}
+
+// Neighborhood of `pubkey` on the global leaderboard, reading only the rows around it
+pub async fn global_neighborhood(
+    pubkey_stats: &Collection<Document>,
+    pubkey: &str,
+    around: usize,
+) -> Result<Option<Neighborhood>, mongodb::error::Error> {
+    let Some(stats) = find_pubkey_stats(pubkey_stats, pubkey).await? else {
+        return Ok(None);
+    };
+    let rank = global_rank(pubkey_stats, &stats).await?;
+    let total = pubkey_stats.count_documents(doc! { "votes": { "$gt": 0 } }, None).await? as usize;
+
+    // Walk upwards from the pubkey, then flip so the list reads top-down
+    let above_filter = doc! {
+        "$or": [
+            { "votes": { "$gt": stats.votes } },
+            { "votes": stats.votes, "_id": { "$lt": &stats.pubkey } },
+        ]
+    };
+    let mut above = stats_rows(pubkey_stats, above_filter, doc! { "votes": 1, "_id": -1 }, around).await?;
+    above.reverse();
+    let first_above = rank - above.len();
+    for (index, row) in above.iter_mut().enumerate() {
+        row.rank = first_above + index;
+    }
+
+    let below_filter = doc! {
+        "$or": [
+            { "votes": { "$lt": stats.votes, "$gt": 0 } },
+            { "votes": stats.votes, "_id": { "$gt": &stats.pubkey } },
+        ]
+    };
+    let mut below = stats_rows(pubkey_stats, below_filter, doc! { "votes": -1, "_id": 1 }, around).await?;
+    for (index, row) in below.iter_mut().enumerate() {
+        row.rank = rank + index + 1;
+    }
+
+    Ok(Some(Neighborhood { pubkey: stats.pubkey, rank, votes: stats.votes, total, above, below }))
+}
+
+// Up to `limit` pubkey_stats rows in `sort` order, ranks left for the caller to fill in
+async fn stats_rows(
+    pubkey_stats: &Collection<Document>,
+    filter: Document,
+    sort: Document,
+    limit: usize,
+) -> Result<Vec<LeaderboardRow>, mongodb::error::Error> {
+    if limit == 0 {
+        return Ok(Vec::new());
+    }
+    let options = mongodb::options::FindOptions::builder().sort(sort).limit(limit as i64).build();
+    let mut cursor = pubkey_stats.find(filter, options).await?;
+    let mut rows = Vec::new();
+    while let Some(document) = cursor.next().await {
+        let stats: PubkeyStats = mongodb::bson::from_document(document?)?;
+        rows.push(LeaderboardRow { rank: 0, pubkey: stats.pubkey, votes: stats.votes });
+    }
+    Ok(rows)
+}
+
+// A row of the accuracy leaderboard
+#[derive(Debug, Serialize)]
+pub struct AccuracyRow {
+    pub rank: usize,
+    pub pubkey: String,
+    pub votes: u32,
+    pub agreed: u32,
+    pub dissented: u32,
+    pub accuracy: f64,
+}
+
+// One page of the leaderboard ordered by consensus accuracy, then by agreeing votes and pubkey.
+// Only pubkeys with at least `min_votes` votes and at least one decided vote are ranked.
+pub async fn page_accuracy(
+    pubkey_stats: &Collection<Document>,
+    min_votes: u32,
+    offset: usize,
+    limit: usize,
+    descending: bool,
+) -> Result<(usize, Vec<AccuracyRow>), mongodb::error::Error> {
+    let filter = doc! {
+        "votes": { "$gte": min_votes.max(1) as i64 },
+        "$or": [{ "agreed": { "$gt": 0 } }, { "dissented": { "$gt": 0 } }],
+    };
+    let total = pubkey_stats.count_documents(filter.clone(), None).await? as usize;
+
+    let direction = if descending { -1 } else { 1 };
+    let pipeline = vec![
+        doc! { "$match": filter },
+        doc! { "$addFields": { "accuracy": { "$divide": ["$agreed", { "$add": ["$agreed", "$dissented"] }] } } },
+        doc! { "$sort": { "accuracy": direction, "agreed": direction, "_id": -direction } },
+        doc! { "$skip": offset as i64 },
+        doc! { "$limit": limit as i64 },
+    ];
+    let options = mongodb::options::AggregateOptions::builder().allow_disk_use(true).build();
+    let mut cursor = pubkey_stats.aggregate(pipeline, options).await?;
+    let mut rows = Vec::new();
+    while let Some(document) = cursor.next().await {
+        let stats: PubkeyStats = mongodb::bson::from_document(document?)?;
+        let position = offset + rows.len();
+        let rank = if descending { position + 1 } else { total - position };
+        rows.push(AccuracyRow {
+            rank,
+            accuracy: stats.accuracy().unwrap_or(0.0),
+            pubkey: stats.pubkey,
+            votes: stats.votes,
+            agreed: stats.agreed,
+            dissented: stats.dissented,
+        });
+    }
+    Ok((total, rows))
+}
+
+// One page of the global leaderboard straight from pubkey_stats, together with the total number
+// of ranked pubkeys. `descending` walks from rank 1 down, otherwise from the last rank up.
+pub async fn page_pubkey_stats(
+    pubkey_stats: &Collection<Document>,
+    offset: usize,
+    limit: usize,
+    descending: bool,
+) -> Result<(usize, Vec<LeaderboardRow>), mongodb::error::Error> {
+    let filter = doc! { "votes": { "$gt": 0 } };
+    let total = pubkey_stats.count_documents(filter.clone(), None).await? as usize;
+
+    let sort = if descending { doc! { "votes": -1, "_id": 1 } } else { doc! { "votes": 1, "_id": -1 } };
+    let options = mongodb::options::FindOptions::builder()
+        .sort(sort)
+        .skip(offset as u64)
+        .limit(limit as i64)
+        .build();
+    let mut cursor = pubkey_stats.find(filter, options).await?;
+    let mut rows = Vec::new();
+    while let Some(document) = cursor.next().await {
+        let stats: PubkeyStats = mongodb::bson::from_document(document?)?;
+        let position = offset + rows.len();
+        let rank = if descending { position + 1 } else { total - position };
+        rows.push(LeaderboardRow { rank, pubkey: stats.pubkey, votes: stats.votes });
+    }
+    Ok((total, rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(votes: &[(&str, u32)]) -> Vec<LeaderboardRow> {
+        rank_votes(votes.iter().map(|(pubkey, votes)| (pubkey.to_string(), *votes)).collect())
+    }
+
+    fn pubkeys(changes: &[RankChange]) -> Vec<&str> {
+        changes.iter().map(|change| change.pubkey.as_str()).collect()
+    }
+
+    #[test]
+    fn ranks_by_votes_then_pubkey() {
+        let ranked = rows(&[("c", 5), ("a", 5), ("b", 9)]);
+        let order: Vec<(usize, &str)> = ranked.iter().map(|row| (row.rank, row.pubkey.as_str())).collect();
+        assert_eq!(order, vec![(1, "b"), (2, "a"), (3, "c")]);
+    }
+
+    #[test]
+    fn neighborhood_is_clipped_at_the_edges() {
+        let ranked = rows(&[("a", 5), ("b", 4), ("c", 3), ("d", 2)]);
+        let top = Neighborhood::from_ranked(&ranked, "a", 2).unwrap();
+        assert_eq!(top.rank, 1);
+        assert_eq!(top.total, 4);
+        assert!(top.above.is_empty());
+        assert_eq!(top.below.iter().map(|row| row.pubkey.as_str()).collect::<Vec<_>>(), vec!["b", "c"]);
+
+        let bottom = Neighborhood::from_ranked(&ranked, "d", 1).unwrap();
+        assert_eq!(bottom.above.iter().map(|row| row.pubkey.as_str()).collect::<Vec<_>>(), vec!["c"]);
+        assert!(bottom.below.is_empty());
+
+        assert!(Neighborhood::from_ranked(&ranked, "missing", 1).is_none());
+    }
+
+    #[test]
+    fn top_changes_lists_moved_and_dropped_rows() {
+        let old = rows(&[("a", 5), ("b", 4), ("c", 3)]);
+        let new = rows(&[("b", 6), ("a", 5), ("d", 4)]);
+        let changes = top_changes(&old, &new);
+        let changes: Vec<(&str, Option<usize>, Option<usize>)> = changes
+            .iter()
+            .map(|change| (change.pubkey.as_str(), change.old_rank, change.new_rank))
+            .collect();
+        assert_eq!(changes, vec![("b", Some(2), Some(1)), ("a", Some(1), Some(2)), ("d", None, Some(3)), ("c", Some(3), None)]);
+
+        assert!(top_changes(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn diff_splits_movers_entrants_and_dropouts() {
+        let before = rows(&[("a", 10), ("b", 8), ("c", 6), ("d", 4)]);
+        let after = rows(&[("c", 12), ("a", 11), ("b", 8), ("e", 5)]);
+        let diff = diff_leaderboards(&before, &after, 10);
+        assert_eq!(pubkeys(&diff.gainers), vec!["c"]);
+        assert_eq!(diff.gainers[0].rank_delta, Some(2));
+        assert_eq!(pubkeys(&diff.losers), vec!["a", "b"]);
+        assert_eq!(pubkeys(&diff.new_entrants), vec!["e"]);
+        assert_eq!(pubkeys(&diff.dropouts), vec!["d"]);
+        assert!(diff.count_changes.is_empty());
+    }
+
+    #[test]
+    fn diff_keeps_count_changes_at_the_same_rank() {
+        let before = rows(&[("a", 10), ("b", 8)]);
+        let after = rows(&[("a", 15), ("b", 8)]);
+        let diff = diff_leaderboards(&before, &after, 10);
+        assert!(diff.gainers.is_empty() && diff.losers.is_empty());
+        assert_eq!(pubkeys(&diff.count_changes), vec!["a"]);
+        assert_eq!(diff.count_changes[0].vote_delta, 5);
+    }
+
+    #[test]
+    fn diff_limits_each_list() {
+        let before = rows(&[("a", 1), ("b", 2), ("c", 3)]);
+        let after = rows(&[("d", 1), ("e", 2), ("f", 3)]);
+        let diff = diff_leaderboards(&before, &after, 2);
+        assert_eq!(pubkeys(&diff.new_entrants), vec!["f", "e"]);
+        assert_eq!(pubkeys(&diff.dropouts), vec!["c", "b"]);
+    }
+}
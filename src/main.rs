@@ -1,42 +1,246 @@
-use tokio::sync::broadcast;
-use mongodb::{Client, IndexModel, options::IndexOptions};
-use mongodb::bson::doc;
+use std::io::Write;
+use std::sync::Arc;
+use clap::Parser;
+use mongodb::{Client, Database, bson::doc};
 
+mod api;
 mod ws;
 mod models;
 mod server;
 mod fetch;
+mod cursor;
+mod db;
+mod event_log;
+mod events;
+mod failed;
+mod forks;
+mod gaps;
+mod hashes;
+mod indexes;
+mod ingest;
+mod retry;
+mod backfill;
+mod config;
+mod cli;
+mod export;
+mod leaderboard;
+mod metrics;
+mod profile;
+mod source;
 mod routes;
+mod snapshots;
+mod subscriptions;
+mod windows;
+
+use cli::{Cli, Command, CursorAction, ExportKind, FailedAction};
+use config::{Config, ConfigError};
 
 #[tokio::main]
 async fn main() {
     // Initialize logging
     env_logger::init();
 
-    // Create a MongoDB client
-    let mongo_uri = "mongodb://localhost:27017";
-    let client = Client::with_uri_str(mongo_uri).await.unwrap();
-    let db = client.database("block_data");
-    let collection = db.collection("blocks");
+    let cli = Cli::parse();
+
+    // Load the configuration file and environment overrides
+    let config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Create a MongoDB client; only the URI is checked here, the server is contacted on first use
+    let client = match Client::with_uri_str(&config.mongo.uri).await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("{}", ConfigError::Invalid(format!("mongo.uri: {}", e)));
+            std::process::exit(1);
+        }
+    };
+    let db = client.database(&config.mongo.database);
+
+    if let Err(e) = run(cli.command.unwrap_or(Command::Serve), db, config).await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(command: Command, db: Database, config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    let collections = db::Collections::new(&db, &config.mongo);
+    let collection = &collections.blocks;
+    let state = &collections.state;
+
+    match command {
+        Command::Serve => {
+            prepare_indexes(&collections).await?;
+            prepare_derived(&collections).await?;
 
-    // Define the index model
-    let index_model = IndexModel::builder()
-        .keys(doc! { "entries.blockId": 1 })  // Specify the index on the nested blockId field within entries
-        .options(IndexOptions::builder().unique(true).build())
-        .build();
+            // Events published by the ingestion loop and fanned out to WebSocket clients
+            let metrics = Arc::new(metrics::Metrics::default());
+            let events = events::EventBus::start(&config.events, collections.events.clone(), collections.state.clone(), metrics.clone()).await?;
 
-    // Create the index
-    collection.create_index(index_model, None).await.unwrap();
+            // Start the WebSocket server in a separate task
+            tokio::spawn(server::ws_server::run_ws_server(events.clone(), config.clone()));
 
-    // Create a broadcast channel
-    let (tx, _rx) = broadcast::channel(100);
+            // Start the HTTP REST server in a separate task
+            let source = source::from_config(&config)?;
+            tokio::spawn(server::http_server::run_http_server(collections.clone(), config.clone(), metrics));
 
-    // Start the WebSocket server in a separate task
-    tokio::spawn(server::ws_server::run_ws_server(tx.clone()));
+            // Start the admin server for the endpoints that change ingestion state
+            if config.server.admin_port > 0 {
+                tokio::spawn(server::admin_server::run_admin_server(collections.clone(), source.clone(), config.clone()));
+            }
 
-    // Start the HTTP REST server in a separate task
-    tokio::spawn(server::http_server::run_http_server(collection.clone()));
+            // Periodically re-fetch blocks missing from the collection
+            if config.gaps.repair_interval_secs > 0 {
+                tokio::spawn(gaps::run_gap_repair(collections.clone(), source.clone(), config.clone()));
+            }
+
+            // Start fetching, broadcasting data, and saving to the database
+            fetch::fetch_data_and_broadcast(events, collections, source, config).await;
+        }
+        Command::Ingest => {
+            prepare_indexes(&collections).await?;
+            prepare_derived(&collections).await?;
+
+            // Nobody subscribes without the WebSocket server, events only go to the event log
+            let metrics = Arc::new(metrics::Metrics::default());
+            let events = events::EventBus::start(&config.events, collections.events.clone(), collections.state.clone(), metrics).await?;
+            let source = source::from_config(&config)?;
+            fetch::fetch_data_and_broadcast(events, collections, source, config).await;
+        }
+        Command::Backfill { from, to, concurrency } => {
+            prepare_indexes(&collections).await?;
+            prepare_derived(&collections).await?;
+
+            let mut config = config;
+            if let Some(concurrency) = concurrency {
+                config.backfill.concurrency = concurrency.max(1);
+            }
+            let source = source::from_config(&config)?;
+            let report = backfill::run_backfill(&collections, source.as_ref(), &config, from, to).await;
+            report.print_summary();
+        }
+        Command::RebuildLeaderboard => {
+            let total = leaderboard::rebuild_pubkey_stats(collection, &collections.pubkey_stats).await?;
+            println!("Rebuilt leaderboard with {} pubkeys.", total);
+            let buckets = windows::rebuild_hourly_stats(collection, &collections.hourly_stats).await?;
+            println!("Rebuilt {} hourly vote buckets.", buckets);
+        }
+        Command::RebuildForks => {
+            let total = forks::rebuild_forks(collection, &collections.forks).await?;
+            println!("Rebuilt fork report with {} divergent entries.", total);
+        }
+        Command::Snapshot => {
+            let info = snapshots::take_snapshot(&collections, None).await?;
+            println!("{}", serde_json::to_string(&info)?);
+        }
+        Command::Reindex { check } => {
+            let drifts = if check {
+                indexes::check_indexes(&collections).await?
+            } else {
+                indexes::ensure_indexes(&collections).await?
+            };
+            for drift in drifts {
+                println!("{}", serde_json::to_string(&drift)?);
+            }
+        }
+        Command::Export { what, from, to, output } => {
+            let filter = models::block_range_filter(from, to);
+            let mut out: Box<dyn Write> = match output {
+                Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            let written = match what {
+                ExportKind::Blocks => export::export_blocks(collection, filter, &mut out).await?,
+                ExportKind::Leaderboard => export::export_leaderboard(collection, filter, &mut out).await?,
+            };
+            out.flush()?;
+            eprintln!("Exported {} records.", written);
+        }
+        Command::Cursor { action } => match action {
+            CursorAction::Show => match cursor::load_cursor(state).await? {
+                Some(cursor) => println!("{}", serde_json::to_string_pretty(&cursor)?),
+                None => println!("No ingestion cursor stored."),
+            },
+            CursorAction::Rewind { block_id } => {
+                let settings = &config.fetch;
+                if !settings.can_rewind_to(block_id) {
+                    return Err(format!("Block ID {} is not part of the configured block range", block_id).into());
+                }
+                cursor::rewind_cursor(state, block_id, settings.start_block_id, settings.range_end()).await?;
+                println!("Ingestion cursor rewound to block ID {}.", block_id);
+            }
+            CursorAction::Reset => {
+                cursor::reset_cursor(state).await?;
+                println!("Ingestion cursor reset.");
+            }
+        },
+        Command::Gaps { from, to, enqueue } => {
+            let report = gaps::find_gaps(collection, &config.fetch, from, to).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if enqueue {
+                let enqueued = gaps::enqueue_gaps(&collections.failed, &report, &config.fetch, None).await?;
+                println!("Enqueued {} missing blocks for re-fetch.", enqueued);
+            }
+        }
+        Command::Failed { action } => match action {
+            FailedAction::List { all } => {
+                for failure in failed::list_failures(&collections.failed, all, None).await? {
+                    println!("{}", serde_json::to_string(&failure)?);
+                }
+            }
+            FailedAction::Retry { all } => {
+                let source = source::from_config(&config)?;
+                let report = failed::retry_failures(&collections, source.as_ref(), &config.retry, all, None).await?;
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+        },
+    }
+    Ok(())
+}
+
+// Fill derived collections that were introduced after the stored blocks were ingested, before
+// ingestion starts adding to them
+async fn prepare_derived(collections: &db::Collections) -> Result<(), mongodb::error::Error> {
+    if collections.blocks.find_one(None, None).await?.is_none() {
+        return Ok(());
+    }
+    // Stats written before agreement was tracked lack the agreed and dissented counts
+    let stats_missing = collections.pubkey_stats.find_one(None, None).await?.is_none()
+        || collections.pubkey_stats.find_one(doc! { "agreed": { "$exists": false } }, None).await?.is_some();
+    if stats_missing {
+        println!("Building the leaderboard from the stored blocks.");
+        let total = leaderboard::rebuild_pubkey_stats(&collections.blocks, &collections.pubkey_stats).await?;
+        println!("Built leaderboard with {} pubkeys.", total);
+    }
+    if collections.hourly_stats.find_one(None, None).await?.is_none() {
+        println!("Building hourly vote buckets from the stored blocks.");
+        let buckets = windows::rebuild_hourly_stats(&collections.blocks, &collections.hourly_stats).await?;
+        println!("Built {} hourly vote buckets.", buckets);
+    }
+    Ok(())
+}
 
-    // Start fetching, broadcasting data, and saving to the database
-    fetch::fetch_data_and_broadcast(tx, collection).await;
+// Migrate indexes before touching any data. A failure is reported but only stops the service
+// when blocks would be stored without the unique blockId index.
+async fn prepare_indexes(collections: &db::Collections) -> Result<(), Box<dyn std::error::Error>> {
+    match indexes::ensure_indexes(collections).await {
+        Ok(drifts) => {
+            for drift in drifts.iter().filter(|drift| !drift.is_clean()) {
+                println!("Index drift on {}: {:?}", drift.collection, drift);
+            }
+        }
+        Err(e) => eprintln!("Error migrating indexes: {}", e),
+    }
+    if !indexes::has_block_id_index(&collections.blocks).await? {
+        return Err(format!(
+            "The unique blockId index is missing on {}; remove duplicate blockId documents and run reindex",
+            collections.blocks.name()
+        )
+        .into());
+    }
+    Ok(())
 }
@@ -0,0 +1,47 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Process-wide counters, exposed in Prometheus text format on GET /metrics
+#[derive(Debug, Default)]
+pub struct Metrics {
+    // Numbered events published on the event bus
+    pub events_published: AtomicU64,
+    // WebSocket clients currently connected
+    pub ws_connections: AtomicU64,
+    // Times a WebSocket client fell behind the broadcast channel
+    pub ws_lagged: AtomicU64,
+    // Events those clients missed as a result
+    pub ws_lagged_events: AtomicU64,
+    // Clients disconnected because they lagged
+    pub ws_lag_disconnects: AtomicU64,
+    // Events left out of the event log because its queue was full, they could not be converted or
+    // the write failed
+    pub event_log_dropped: AtomicU64,
+    // Events numbered beyond the sequence numbers reserved in the stored counter
+    pub events_unreserved: AtomicU64,
+}
+
+impl Metrics {
+    pub fn increment(counter: &AtomicU64, by: u64) {
+        counter.fetch_add(by, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let metrics = [
+            ("xenvoter_events_published_total", "counter", "Events published to WebSocket subscribers", &self.events_published),
+            ("xenvoter_ws_connections", "gauge", "Connected WebSocket clients", &self.ws_connections),
+            ("xenvoter_ws_lagged_total", "counter", "Times a WebSocket client fell behind", &self.ws_lagged),
+            ("xenvoter_ws_lagged_events_total", "counter", "Events skipped by lagging WebSocket clients", &self.ws_lagged_events),
+            ("xenvoter_ws_lag_disconnects_total", "counter", "WebSocket clients disconnected for lagging", &self.ws_lag_disconnects),
+            ("xenvoter_event_log_dropped_total", "counter", "Events that could not be written to the event log", &self.event_log_dropped),
+            ("xenvoter_events_unreserved_total", "counter", "Events numbered before their sequence number was reserved", &self.events_unreserved),
+        ];
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+        out
+    }
+}
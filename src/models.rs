@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
-use mongodb::bson::{Document, doc};
+use mongodb::bson::{doc, Bson, Document};
+use sha2::{Digest, Sha256};
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Block {
     #[serde(rename = "blockId")]
     pub block_id: u32,
@@ -9,7 +10,7 @@ pub struct Block {
     pub entries: Vec<Entry>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Entry {
     #[serde(rename = "blockId")]
     pub block_id: String,
@@ -17,7 +18,7 @@ pub struct Entry {
     pub final_hashes: Vec<FinalHash>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct FinalHash {
     #[serde(rename = "finalHash")]
     pub final_hash: String,
@@ -36,6 +37,12 @@ impl Block {
         doc.insert("entries", entries);
         doc
     }
+
+    // SHA-256 over the JSON serialization, used to detect upstream changes to a stored block
+    pub fn content_hash(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        format!("{:x}", Sha256::digest(&json))
+    }
 }
 
 impl Entry {
@@ -47,6 +54,22 @@ impl Entry {
         doc.insert("finalHashes", final_hashes);
         doc
     }
+
+    // The final hash with the highest count, or None when no single hash has the most votes
+    pub fn consensus_hash(&self) -> Option<&FinalHash> {
+        let top = self.final_hashes.iter().max_by_key(|fh| fh.count)?;
+        let shared = self.final_hashes.iter().filter(|fh| fh.count == top.count).count() > 1;
+        (!shared).then_some(top)
+    }
+
+    // Every vote in the entry, with whether it went to the consensus hash (None without consensus)
+    pub fn votes(&self) -> impl Iterator<Item = (&str, Option<bool>)> {
+        let consensus = self.consensus_hash().map(|fh| fh.final_hash.as_str());
+        self.final_hashes.iter().flat_map(move |fh| {
+            let agreed = consensus.map(|hash| hash == fh.final_hash);
+            fh.pubkeys.iter().map(move |pubkey| (pubkey.as_str(), agreed))
+        })
+    }
 }
 
 impl FinalHash {
@@ -59,3 +82,66 @@ impl FinalHash {
         doc
     }
 }
+
+// Read a stored blockId, which is written as a 64-bit integer from the u32 field
+pub fn bson_block_id(value: &Bson) -> Option<i32> {
+    match value {
+        Bson::Int32(id) => Some(*id),
+        Bson::Int64(id) => i32::try_from(*id).ok(),
+        _ => None,
+    }
+}
+
+// Filter on the top-level blockId for the optional [from, to] bounds
+pub fn block_range_filter(from: Option<i32>, to: Option<i32>) -> Document {
+    let mut range = Document::new();
+    if let Some(from) = from {
+        range.insert("$gte", from);
+    }
+    if let Some(to) = to {
+        range.insert("$lte", to);
+    }
+    if range.is_empty() {
+        doc! {}
+    } else {
+        doc! { "blockId": range }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn final_hash(hash: &str, pubkeys: &[&str]) -> FinalHash {
+        FinalHash {
+            final_hash: hash.to_string(),
+            count: pubkeys.len() as u32,
+            pubkeys: pubkeys.iter().map(|pubkey| pubkey.to_string()).collect(),
+        }
+    }
+
+    fn entry(final_hashes: Vec<FinalHash>) -> Entry {
+        Entry { block_id: "1-0".to_string(), final_hashes }
+    }
+
+    #[test]
+    fn consensus_is_the_hash_with_most_votes() {
+        let entry = entry(vec![final_hash("a", &["p1"]), final_hash("b", &["p2", "p3"])]);
+        assert_eq!(entry.consensus_hash().map(|fh| fh.final_hash.as_str()), Some("b"));
+
+        let votes: Vec<(&str, Option<bool>)> = entry.votes().collect();
+        assert_eq!(votes, vec![("p1", Some(false)), ("p2", Some(true)), ("p3", Some(true))]);
+    }
+
+    #[test]
+    fn no_consensus_on_a_tie() {
+        let entry = entry(vec![final_hash("a", &["p1"]), final_hash("b", &["p2"])]);
+        assert!(entry.consensus_hash().is_none());
+        assert!(entry.votes().all(|(_, agreed)| agreed.is_none()));
+    }
+
+    #[test]
+    fn no_consensus_without_hashes() {
+        assert!(entry(Vec::new()).consensus_hash().is_none());
+    }
+}
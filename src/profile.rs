@@ -0,0 +1,110 @@
+use futures_util::StreamExt;
+use mongodb::{Collection, options::FindOptions, bson::{doc, Document}};
+use serde::Serialize;
+
+use crate::db::Collections;
+use crate::leaderboard;
+use crate::models::Block;
+
+// Everything known about a single voter
+#[derive(Debug, Serialize)]
+pub struct PubkeyProfile {
+    pub pubkey: String,
+    pub votes: u32,
+    // Position on the global leaderboard, ties broken by pubkey
+    pub rank: usize,
+    #[serde(rename = "firstBlockId")]
+    pub first_block_id: u32,
+    #[serde(rename = "lastBlockId")]
+    pub last_block_id: u32,
+    #[serde(rename = "distinctFinalHashes")]
+    pub distinct_final_hashes: u64,
+    // Votes for the consensus hash of their entry versus a minority hash
+    pub agreed: u32,
+    pub dissented: u32,
+    pub accuracy: Option<f64>,
+}
+
+// A block the pubkey voted in, with the final hashes it voted for there
+#[derive(Debug, Serialize)]
+pub struct PubkeyBlock {
+    #[serde(rename = "blockId")]
+    pub block_id: u32,
+    pub votes: u32,
+    #[serde(rename = "finalHashes")]
+    pub final_hashes: Vec<String>,
+}
+
+fn voted_in(pubkey: &str) -> Document {
+    doc! { "entries.finalHashes.pubkeys": pubkey }
+}
+
+// Summary for `pubkey`, or None when it never voted
+pub async fn load_profile(collections: &Collections, pubkey: &str) -> Result<Option<PubkeyProfile>, mongodb::error::Error> {
+    let Some(stats) = leaderboard::find_pubkey_stats(&collections.pubkey_stats, pubkey).await? else {
+        return Ok(None);
+    };
+    let rank = leaderboard::global_rank(&collections.pubkey_stats, &stats).await?;
+    let distinct_final_hashes = count_distinct_final_hashes(&collections.blocks, pubkey).await?;
+    Ok(Some(PubkeyProfile {
+        accuracy: stats.accuracy(),
+        pubkey: stats.pubkey,
+        votes: stats.votes,
+        rank,
+        first_block_id: stats.first_block_id,
+        last_block_id: stats.last_block_id,
+        distinct_final_hashes,
+        agreed: stats.agreed,
+        dissented: stats.dissented,
+    }))
+}
+
+async fn count_distinct_final_hashes(blocks: &Collection<Document>, pubkey: &str) -> Result<u64, mongodb::error::Error> {
+    let pipeline = vec![
+        doc! { "$match": voted_in(pubkey) },
+        doc! { "$unwind": "$entries" },
+        doc! { "$unwind": "$entries.finalHashes" },
+        doc! { "$match": { "entries.finalHashes.pubkeys": pubkey } },
+        doc! { "$group": { "_id": "$entries.finalHashes.finalHash" } },
+        doc! { "$count": "distinct" },
+    ];
+    let mut cursor = blocks.aggregate(pipeline, None).await?;
+    match cursor.next().await {
+        Some(document) => Ok(document?.get_i32("distinct").unwrap_or(0) as u64),
+        None => Ok(0),
+    }
+}
+
+// One page of the blocks `pubkey` voted in, ordered by block ID, with the total number of such blocks
+pub async fn page_pubkey_blocks(
+    blocks: &Collection<Document>,
+    pubkey: &str,
+    offset: usize,
+    limit: usize,
+    descending: bool,
+) -> Result<(usize, Vec<PubkeyBlock>), mongodb::error::Error> {
+    let total = blocks.count_documents(voted_in(pubkey), None).await? as usize;
+
+    let options = FindOptions::builder()
+        .sort(doc! { "blockId": if descending { -1 } else { 1 } })
+        .skip(offset as u64)
+        .limit(limit as i64)
+        .build();
+    let mut cursor = blocks.find(voted_in(pubkey), options).await?;
+    let mut items = Vec::new();
+    while let Some(document) = cursor.next().await {
+        let block: Block = mongodb::bson::from_document(document?)?;
+        let votes = leaderboard::block_vote_counts(&block).get(pubkey).map_or(0, |counts| counts.votes);
+        let mut final_hashes: Vec<String> = block
+            .entries
+            .iter()
+            .flat_map(|entry| &entry.final_hashes)
+            .filter(|fh| fh.pubkeys.iter().any(|p| p == pubkey))
+            .map(|fh| fh.final_hash.clone())
+            .collect();
+        final_hashes.sort();
+        final_hashes.dedup();
+        items.push(PubkeyBlock { block_id: block.block_id, votes, final_hashes });
+    }
+    Ok((total, items))
+}
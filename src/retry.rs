@@ -0,0 +1,76 @@
+use std::time::Duration;
+use rand::Rng;
+use tokio::time::sleep;
+
+use crate::api::FetchError;
+use crate::config::RetryConfig;
+use crate::source::{BlockFetch, BlockSource};
+
+// Fetch a block, retrying retryable errors with exponential backoff and jitter
+pub async fn fetch_with_retry(
+    source: &dyn BlockSource,
+    block_id: i32,
+    settings: &RetryConfig,
+) -> Result<BlockFetch, FetchError> {
+    let mut attempt = 1;
+    loop {
+        match source.fetch_block(block_id).await {
+            Err(e) if e.is_retryable() && attempt < settings.max_attempts => {
+                let delay = backoff_delay(settings, attempt);
+                eprintln!(
+                    "Attempt {}/{} for block ID {} failed: {}. Retrying in {:?}.",
+                    attempt, settings.max_attempts, block_id, e, delay
+                );
+                sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Exponential backoff capped at max_backoff_ms, with the configured fraction randomized away
+fn backoff_delay(settings: &RetryConfig, attempt: u32) -> Duration {
+    let exponential = settings
+        .initial_backoff_ms
+        .saturating_mul(1u64 << (attempt - 1).min(32))
+        .min(settings.max_backoff_ms);
+    let jitter = (exponential as f64 * settings.jitter * rand::thread_rng().gen::<f64>()) as u64;
+    Duration::from_millis(exponential - jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MockSource;
+
+    fn settings(jitter: f64) -> RetryConfig {
+        RetryConfig { max_attempts: 5, initial_backoff_ms: 100, max_backoff_ms: 1000, jitter }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let settings = settings(0.0);
+        let delays: Vec<u64> = (1..=6).map(|attempt| backoff_delay(&settings, attempt).as_millis() as u64).collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+        // Large attempt numbers saturate instead of overflowing
+        assert_eq!(backoff_delay(&settings, 100).as_millis(), 1000);
+    }
+
+    #[test]
+    fn jitter_only_shortens_the_delay() {
+        let settings = settings(0.5);
+        for _ in 0..100 {
+            let delay = backoff_delay(&settings, 3).as_millis();
+            assert!((200..=400).contains(&delay), "{}", delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn fetches_from_the_mock_source() {
+        let source = MockSource::synthetic(7, 1, 1);
+        let settings = settings(0.0);
+        assert!(matches!(fetch_with_retry(&source, 7, &settings).await, Ok(BlockFetch::Found(block)) if block.block_id == 7));
+        assert!(matches!(fetch_with_retry(&source, 8, &settings).await, Ok(BlockFetch::Missing)));
+    }
+}
@@ -0,0 +1,51 @@
+use warp::Filter;
+use mongodb::{Collection, bson::Document};
+use warp::reply::{json, with_status};
+use serde::Deserialize;
+use serde_json::json;
+use log::error;
+use crate::leaderboard;
+use crate::routes::pagination::{Page, PageQuery, SortOrder};
+
+#[derive(Debug, Deserialize)]
+pub struct AccuracyQuery {
+    // Leave out pubkeys with fewer votes, whose accuracy says little
+    pub min_votes: Option<u32>,
+}
+
+pub fn get_accuracy_leaderboard(
+    pubkey_stats: Collection<Document>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("leaderboard" / "accuracy")
+        .and(warp::get())
+        .and(warp::query::<PageQuery>())
+        .and(warp::query::<AccuracyQuery>())
+        .and(with_collection(pubkey_stats))
+        .and_then(handle_get_accuracy_leaderboard)
+}
+
+fn with_collection(
+    collection: Collection<Document>,
+) -> impl Filter<Extract = (Collection<Document>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || collection.clone())
+}
+
+async fn handle_get_accuracy_leaderboard(
+    query: PageQuery,
+    accuracy_query: AccuracyQuery,
+    pubkey_stats: Collection<Document>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let min_votes = accuracy_query.min_votes.unwrap_or(1);
+    let descending = query.order == SortOrder::Desc;
+    match leaderboard::page_accuracy(&pubkey_stats, min_votes, query.offset(), query.limit(), descending).await {
+        Ok((total, rows)) => {
+            let response = json(&Page::new(total, &query, rows));
+            Ok(with_status(response, warp::http::StatusCode::OK))
+        }
+        Err(e) => {
+            error!("Error querying MongoDB: {:?}", e);
+            let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
+            Ok(with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use warp::Filter;
+use mongodb::{Collection, bson::{doc, Document}};
+use warp::reply::{json, with_status};
+use serde::Deserialize;
+use serde_json::json;
+use log::error;
+use crate::leaderboard::{self, LeaderboardRow};
+use crate::routes::pagination::{DEFAULT_LIMIT, MAX_LIMIT};
+use crate::windows::{self, TimeWindow, WindowError};
+
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    // Rows kept per list (gainers, losers, new entrants, dropouts, count changes)
+    pub limit: Option<usize>,
+}
+
+impl DiffQuery {
+    fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WindowDiffQuery {
+    // Compare the named window ending at `until` (or now) with the one right before it
+    pub window: Option<String>,
+    pub until: Option<String>,
+    // Or give both windows explicitly, as RFC 3339 or Unix seconds
+    pub before_since: Option<String>,
+    pub before_until: Option<String>,
+    pub after_since: Option<String>,
+    pub after_until: Option<String>,
+}
+
+impl WindowDiffQuery {
+    fn resolve(&self) -> Result<(TimeWindow, TimeWindow), WindowError> {
+        if self.window.is_some() {
+            let after = TimeWindow::resolve(None, self.until.as_deref(), self.window.as_deref())?;
+            return Ok((after.preceding(), after));
+        }
+        let before = TimeWindow::resolve(self.before_since.as_deref(), self.before_until.as_deref(), None)?;
+        let after = TimeWindow::resolve(self.after_since.as_deref(), self.after_until.as_deref(), None)?;
+        Ok((before, after))
+    }
+}
+
+// GET /blocks/{start}/{end}/diff/{start}/{end}: movers between two block range leaderboards
+pub fn get_range_diff(
+    collection: Collection<Document>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("blocks" / i32 / i32 / "diff" / i32 / i32)
+        .and(warp::get())
+        .and(warp::query::<DiffQuery>())
+        .and(with_collection(collection))
+        .and_then(handle_get_range_diff)
+}
+
+// GET /leaderboard/window/diff: movers between two time windows
+pub fn get_window_diff(
+    hourly_stats: Collection<Document>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("leaderboard" / "window" / "diff")
+        .and(warp::get())
+        .and(warp::query::<WindowDiffQuery>())
+        .and(warp::query::<DiffQuery>())
+        .and(with_collection(hourly_stats))
+        .and_then(handle_get_window_diff)
+}
+
+fn with_collection(
+    collection: Collection<Document>,
+) -> impl Filter<Extract = (Collection<Document>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || collection.clone())
+}
+
+async fn handle_get_range_diff(
+    before_start: i32,
+    before_end: i32,
+    after_start: i32,
+    after_end: i32,
+    query: DiffQuery,
+    collection: Collection<Document>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if before_start > before_end || after_start > after_end {
+        return Ok(with_status(
+            json(&json!({"error": "Invalid range: start_id is greater than end_id"})),
+            warp::http::StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let before = range_leaderboard(&collection, before_start, before_end).await;
+    let after = range_leaderboard(&collection, after_start, after_end).await;
+    match before.and_then(|before| after.map(|after| (before, after))) {
+        Ok((before, after)) => {
+            let response = json(&json!({
+                "before": { "start": before_start, "end": before_end, "total": before.len() },
+                "after": { "start": after_start, "end": after_end, "total": after.len() },
+                "diff": leaderboard::diff_leaderboards(&before, &after, query.limit()),
+            }));
+            Ok(with_status(response, warp::http::StatusCode::OK))
+        }
+        Err(e) => Ok(internal_error(e)),
+    }
+}
+
+async fn handle_get_window_diff(
+    window_query: WindowDiffQuery,
+    query: DiffQuery,
+    hourly_stats: Collection<Document>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (before_window, after_window) = match window_query.resolve() {
+        Ok(windows) => windows,
+        Err(e) => {
+            return Ok(with_status(json(&json!({"error": e.to_string()})), warp::http::StatusCode::BAD_REQUEST));
+        }
+    };
+
+    let before = windows::window_votes(&hourly_stats, &before_window).await;
+    let after = windows::window_votes(&hourly_stats, &after_window).await;
+    match before.and_then(|before| after.map(|after| (before, after))) {
+        Ok((before, after)) => {
+            let before = leaderboard::rank_votes(before);
+            let after = leaderboard::rank_votes(after);
+            let response = json(&json!({
+                "before": { "window": before_window, "total": before.len() },
+                "after": { "window": after_window, "total": after.len() },
+                "diff": leaderboard::diff_leaderboards(&before, &after, query.limit()),
+            }));
+            Ok(with_status(response, warp::http::StatusCode::OK))
+        }
+        Err(e) => Ok(internal_error(e)),
+    }
+}
+
+// Ranked leaderboard of a block range, counted the same way as /blocks/{start}/{end}
+async fn range_leaderboard(
+    collection: &Collection<Document>,
+    start_id: i32,
+    end_id: i32,
+) -> Result<Vec<LeaderboardRow>, mongodb::error::Error> {
+    let filter = doc! {
+        "blockId": { "$gte": start_id, "$lte": end_id }
+    };
+    Ok(leaderboard::rank(leaderboard::tally_pubkeys(collection, filter).await?))
+}
+
+fn internal_error(e: mongodb::error::Error) -> warp::reply::WithStatus<warp::reply::Json> {
+    error!("Error querying MongoDB: {:?}", e);
+    let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
+    with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+}
@@ -0,0 +1,76 @@
+use std::sync::Arc;
+use warp::Filter;
+use mongodb::{Collection, bson::Document};
+use warp::reply::{json, with_status};
+use serde::Deserialize;
+use serde_json::json;
+use log::{error, info};
+use crate::config::RetryConfig;
+use crate::db::Collections;
+use crate::failed::{self, RetryJob};
+use crate::source::BlockSource;
+
+#[derive(Debug, Deserialize)]
+pub struct FailedQuery {
+    // Include failures recorded as permanent
+    #[serde(default)]
+    pub all: bool,
+}
+
+pub fn get_failed_blocks(
+    failed: Collection<Document>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("ingest" / "failed")
+        .and(warp::get())
+        .and(warp::query::<FailedQuery>())
+        .and(warp::any().map(move || failed.clone()))
+        .and_then(handle_get_failed_blocks)
+}
+
+pub fn retry_failed_blocks(
+    collections: Collections,
+    source: Arc<dyn BlockSource>,
+    settings: RetryConfig,
+    job: Arc<RetryJob>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("ingest" / "failed" / "retry")
+        .and(warp::post())
+        .and(warp::query::<FailedQuery>())
+        .and(warp::any().map(move || (collections.clone(), source.clone(), settings.clone(), job.clone())))
+        .and_then(handle_retry_failed_blocks)
+}
+
+pub fn get_retry_status(
+    job: Arc<RetryJob>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("ingest" / "failed" / "retry")
+        .and(warp::get())
+        .map(move || json(&job.status()))
+}
+
+async fn handle_get_failed_blocks(
+    query: FailedQuery,
+    failed: Collection<Document>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match failed::list_failures(&failed, query.all, None).await {
+        Ok(failures) => Ok(with_status(json(&failures), warp::http::StatusCode::OK)),
+        Err(e) => {
+            error!("Error querying MongoDB: {:?}", e);
+            let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
+            Ok(with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+// Starts the retry in the background; its progress is read back with GET on the same path
+async fn handle_retry_failed_blocks(
+    query: FailedQuery,
+    (collections, source, settings, job): (Collections, Arc<dyn BlockSource>, RetryConfig, Arc<RetryJob>),
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if job.start(collections, source, settings, query.all) {
+        info!("Started retrying failed blocks");
+        Ok(with_status(json(&json!({"started": true})), warp::http::StatusCode::ACCEPTED))
+    } else {
+        Ok(with_status(json(&json!({"error": "A retry is already running"})), warp::http::StatusCode::CONFLICT))
+    }
+}
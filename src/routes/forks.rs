@@ -0,0 +1,61 @@
+use warp::Filter;
+use mongodb::{Collection, bson::Document};
+use warp::reply::{json, with_status};
+use serde::Deserialize;
+use serde_json::json;
+use log::error;
+use crate::forks;
+use crate::models::block_range_filter;
+use crate::routes::pagination::{Page, PageQuery, SortOrder};
+
+#[derive(Debug, Deserialize)]
+pub struct ForksQuery {
+    pub from: Option<i32>,
+    pub to: Option<i32>,
+}
+
+pub fn get_forks(
+    forks: Collection<Document>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("forks")
+        .and(warp::get())
+        .and(warp::query::<ForksQuery>())
+        .and(warp::query::<PageQuery>())
+        .and(with_collection(forks))
+        .and_then(handle_get_forks)
+}
+
+fn with_collection(
+    collection: Collection<Document>,
+) -> impl Filter<Extract = (Collection<Document>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || collection.clone())
+}
+
+async fn handle_get_forks(
+    range: ForksQuery,
+    query: PageQuery,
+    forks: Collection<Document>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if let (Some(from), Some(to)) = (range.from, range.to) {
+        if from > to {
+            return Ok(with_status(
+                json(&json!({"error": "Invalid range: from is greater than to"})),
+                warp::http::StatusCode::BAD_REQUEST,
+            ));
+        }
+    }
+
+    let filter = block_range_filter(range.from, range.to);
+    let descending = query.order == SortOrder::Desc;
+    match forks::list_forks(&forks, filter, query.offset(), query.limit(), descending).await {
+        Ok((total, items)) => {
+            let response = json(&Page::new(total, &query, items));
+            Ok(with_status(response, warp::http::StatusCode::OK))
+        }
+        Err(e) => {
+            error!("Error querying MongoDB: {:?}", e);
+            let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
+            Ok(with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use warp::Filter;
+use mongodb::{Collection, bson::Document};
+use warp::reply::{json, with_status, Json, WithStatus};
+use serde::Deserialize;
+use serde_json::json;
+use log::{error, info};
+use crate::config::FetchConfig;
+use crate::db::Collections;
+use crate::gaps::{self, GapsError};
+
+#[derive(Debug, Deserialize)]
+pub struct GapsQuery {
+    pub from: Option<i32>,
+    pub to: Option<i32>,
+}
+
+pub fn get_gaps(
+    collection: Collection<Document>,
+    settings: FetchConfig,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gaps")
+        .and(warp::get())
+        .and(warp::query::<GapsQuery>())
+        .and(warp::any().map(move || (collection.clone(), settings.clone())))
+        .and_then(handle_get_gaps)
+}
+
+pub fn enqueue_gaps(
+    collections: Collections,
+    settings: FetchConfig,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gaps" / "enqueue")
+        .and(warp::post())
+        .and(warp::query::<GapsQuery>())
+        .and(warp::any().map(move || (collections.clone(), settings.clone())))
+        .and_then(handle_enqueue_gaps)
+}
+
+async fn handle_get_gaps(
+    query: GapsQuery,
+    (collection, settings): (Collection<Document>, FetchConfig),
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Some(reply) = invalid_range(&query) {
+        return Ok(reply);
+    }
+
+    match gaps::find_gaps(&collection, &settings, query.from, query.to).await {
+        Ok(report) => Ok(with_status(json(&report), warp::http::StatusCode::OK)),
+        Err(e) => Ok(error_reply(e)),
+    }
+}
+
+async fn handle_enqueue_gaps(
+    query: GapsQuery,
+    (collections, settings): (Collections, FetchConfig),
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Some(reply) = invalid_range(&query) {
+        return Ok(reply);
+    }
+
+    let report = match gaps::find_gaps(&collections.blocks, &settings, query.from, query.to).await {
+        Ok(report) => report,
+        Err(e) => return Ok(error_reply(e)),
+    };
+    match gaps::enqueue_gaps(&collections.failed, &report, &settings, None).await {
+        Ok(enqueued) => {
+            info!("Enqueued {} missing blocks between {} and {}", enqueued, report.from, report.to);
+            Ok(with_status(json(&json!({"missing": report.missing, "enqueued": enqueued})), warp::http::StatusCode::OK))
+        }
+        Err(e) => Ok(error_reply(e.into())),
+    }
+}
+
+fn invalid_range(query: &GapsQuery) -> Option<WithStatus<Json>> {
+    match (query.from, query.to) {
+        (Some(from), Some(to)) if from > to => Some(with_status(
+            json(&json!({"error": "Invalid range: from is greater than to"})),
+            warp::http::StatusCode::BAD_REQUEST,
+        )),
+        _ => None,
+    }
+}
+
+fn error_reply(e: GapsError) -> WithStatus<Json> {
+    match e {
+        GapsError::TooLarge { .. } => with_status(json(&json!({"error": e.to_string()})), warp::http::StatusCode::BAD_REQUEST),
+        GapsError::Mongo(e) => {
+            error!("Error querying MongoDB: {:?}", e);
+            let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
+            with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
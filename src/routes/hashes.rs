@@ -0,0 +1,47 @@
+use warp::Filter;
+use mongodb::{Collection, bson::Document};
+use warp::reply::{json, with_status};
+use serde_json::json;
+use log::error;
+use crate::hashes;
+
+pub fn get_final_hash(
+    collection: Collection<Document>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("hashes" / String)
+        .and(warp::get())
+        .and(with_collection(collection))
+        .and_then(handle_get_final_hash)
+}
+
+fn with_collection(
+    collection: Collection<Document>,
+) -> impl Filter<Extract = (Collection<Document>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || collection.clone())
+}
+
+async fn handle_get_final_hash(
+    final_hash: String,
+    collection: Collection<Document>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match hashes::find_final_hash(&collection, &final_hash).await {
+        Ok(occurrences) if occurrences.is_empty() => {
+            let not_found_reply = json(&json!({"error": "Final hash not found"}));
+            Ok(with_status(not_found_reply, warp::http::StatusCode::NOT_FOUND))
+        }
+        Ok(occurrences) => {
+            let votes: u32 = occurrences.iter().map(|occurrence| occurrence.count).sum();
+            let response = json(&json!({
+                "finalHash": final_hash,
+                "votes": votes,
+                "occurrences": occurrences,
+            }));
+            Ok(with_status(response, warp::http::StatusCode::OK))
+        }
+        Err(e) => {
+            error!("Error querying MongoDB: {:?}", e);
+            let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
+            Ok(with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use warp::Filter;
+use mongodb::{Collection, bson::Document};
+use warp::reply::{json, with_status};
+use serde_json::json;
+use log::{error, info};
+use crate::cursor;
+use crate::config::FetchConfig;
+
+pub fn get_ingest_cursor(
+    state: Collection<Document>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("ingest" / "cursor")
+        .and(warp::get())
+        .and(with_collection(state))
+        .and_then(handle_get_ingest_cursor)
+}
+
+pub fn rewind_ingest_cursor(
+    state: Collection<Document>,
+    settings: FetchConfig,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("ingest" / "cursor" / i32)
+        .and(warp::put())
+        .and(with_collection(state))
+        .and(warp::any().map(move || settings.clone()))
+        .and_then(handle_rewind_ingest_cursor)
+}
+
+pub fn reset_ingest_cursor(
+    state: Collection<Document>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("ingest" / "cursor")
+        .and(warp::delete())
+        .and(with_collection(state))
+        .and_then(handle_reset_ingest_cursor)
+}
+
+fn with_collection(
+    collection: Collection<Document>,
+) -> impl Filter<Extract = (Collection<Document>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || collection.clone())
+}
+
+async fn handle_get_ingest_cursor(
+    state: Collection<Document>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match cursor::load_cursor(&state).await {
+        Ok(Some(cursor)) => Ok(with_status(json(&cursor), warp::http::StatusCode::OK)),
+        Ok(None) => {
+            let not_found_reply = json(&json!({"error": "Ingestion cursor not found"}));
+            Ok(with_status(not_found_reply, warp::http::StatusCode::NOT_FOUND))
+        }
+        Err(e) => {
+            error!("Error querying MongoDB: {:?}", e);
+            let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
+            Ok(with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+async fn handle_rewind_ingest_cursor(
+    block_id: i32,
+    state: Collection<Document>,
+    settings: FetchConfig,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !settings.can_rewind_to(block_id) {
+        return Ok(with_status(
+            json(&json!({"error": "Invalid block ID: not part of the configured block range"})),
+            warp::http::StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    match cursor::rewind_cursor(&state, block_id, settings.start_block_id, settings.range_end()).await {
+        Ok(()) => {
+            info!("Ingestion cursor rewound to block ID {}", block_id);
+            Ok(with_status(json(&json!({"nextBlockId": block_id})), warp::http::StatusCode::OK))
+        }
+        Err(e) => {
+            error!("Error updating MongoDB: {:?}", e);
+            let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
+            Ok(with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+async fn handle_reset_ingest_cursor(
+    state: Collection<Document>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match cursor::reset_cursor(&state).await {
+        Ok(_) => {
+            info!("Ingestion cursor reset");
+            Ok(with_status(json(&json!({"message": "Ingestion cursor reset"})), warp::http::StatusCode::OK))
+        }
+        Err(e) => {
+            error!("Error updating MongoDB: {:?}", e);
+            let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
+            Ok(with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
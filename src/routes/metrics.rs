@@ -0,0 +1,14 @@
+use std::sync::Arc;
+use warp::Filter;
+
+use crate::metrics::Metrics;
+
+pub fn get_metrics(
+    metrics: Arc<Metrics>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("metrics")
+        .and(warp::get())
+        .map(move || {
+            warp::reply::with_header(metrics.render(), "content-type", "text/plain; version=0.0.4")
+        })
+}
@@ -1,7 +1,32 @@
 pub mod block;
 pub mod pubkeys;
 pub mod pubkey_ranges;
+pub mod pubkey_profile;
+pub mod neighborhood;
+pub mod accuracy;
+pub mod forks;
+pub mod hashes;
+pub mod windows;
+pub mod diff;
+pub mod snapshots;
+pub mod metrics;
+pub mod ingest;
+pub mod failed;
+pub mod gaps;
+pub mod pagination;
 
 pub use block::get_block_by_id;
 pub use pubkeys::get_all_pubkey_counts;
 pub use pubkey_ranges::get_blocks_in_range;
+pub use pubkey_profile::get_pubkey_profile;
+pub use neighborhood::{get_global_neighborhood, get_range_neighborhood};
+pub use accuracy::get_accuracy_leaderboard;
+pub use forks::get_forks;
+pub use hashes::get_final_hash;
+pub use windows::get_window_leaderboard;
+pub use diff::{get_range_diff, get_window_diff};
+pub use snapshots::{get_snapshots, get_snapshot_by_id};
+pub use ingest::{get_ingest_cursor, rewind_ingest_cursor, reset_ingest_cursor};
+pub use failed::{get_failed_blocks, retry_failed_blocks, get_retry_status};
+pub use gaps::{get_gaps, enqueue_gaps};
+pub use metrics::get_metrics;
@@ -0,0 +1,96 @@
+use warp::Filter;
+use mongodb::{Collection, bson::{doc, Document}};
+use warp::reply::{json, with_status};
+use serde::Deserialize;
+use serde_json::json;
+use log::error;
+use crate::leaderboard::{self, Neighborhood};
+
+// Rows returned on each side when the request does not set `around`
+const DEFAULT_AROUND: usize = 5;
+const MAX_AROUND: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct NeighborhoodQuery {
+    pub around: Option<usize>,
+}
+
+impl NeighborhoodQuery {
+    fn around(&self) -> usize {
+        self.around.unwrap_or(DEFAULT_AROUND).min(MAX_AROUND)
+    }
+}
+
+// GET /pubkeys/{pubkey}/neighborhood?around=N on the global leaderboard
+pub fn get_global_neighborhood(
+    pubkey_stats: Collection<Document>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("pubkeys" / String / "neighborhood")
+        .and(warp::get())
+        .and(warp::query::<NeighborhoodQuery>())
+        .and(with_collection(pubkey_stats))
+        .and_then(handle_get_global_neighborhood)
+}
+
+// GET /blocks/{start}/{end}/neighborhood/{pubkey}?around=N on the leaderboard of a block range
+pub fn get_range_neighborhood(
+    collection: Collection<Document>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("blocks" / i32 / i32 / "neighborhood" / String)
+        .and(warp::get())
+        .and(warp::query::<NeighborhoodQuery>())
+        .and(with_collection(collection))
+        .and_then(handle_get_range_neighborhood)
+}
+
+fn with_collection(
+    collection: Collection<Document>,
+) -> impl Filter<Extract = (Collection<Document>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || collection.clone())
+}
+
+async fn handle_get_global_neighborhood(
+    pubkey: String,
+    query: NeighborhoodQuery,
+    pubkey_stats: Collection<Document>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let result = leaderboard::global_neighborhood(&pubkey_stats, &pubkey, query.around()).await;
+    Ok(neighborhood_reply(result))
+}
+
+async fn handle_get_range_neighborhood(
+    start_id: i32,
+    end_id: i32,
+    pubkey: String,
+    query: NeighborhoodQuery,
+    collection: Collection<Document>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if start_id > end_id {
+        return Ok(with_status(
+            json(&json!({"error": "Invalid range: start_id is greater than end_id"})),
+            warp::http::StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let filter = doc! {
+        "blockId": { "$gte": start_id, "$lte": end_id }
+    };
+    let result = leaderboard::tally_pubkeys(&collection, filter)
+        .await
+        .map(|tallies| Neighborhood::from_ranked(&leaderboard::rank(tallies), &pubkey, query.around()));
+    Ok(neighborhood_reply(result))
+}
+
+fn neighborhood_reply(
+    result: Result<Option<Neighborhood>, mongodb::error::Error>,
+) -> warp::reply::WithStatus<warp::reply::Json> {
+    match result {
+        Ok(Some(neighborhood)) => with_status(json(&neighborhood), warp::http::StatusCode::OK),
+        Ok(None) => with_status(json(&json!({"error": "Pubkey not found"})), warp::http::StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Error querying MongoDB: {:?}", e);
+            let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
+            with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+// Page size used when the request does not set `limit`
+pub const DEFAULT_LIMIT: usize = 100;
+// Largest page a single request may ask for
+pub const MAX_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+impl PageQuery {
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset.unwrap_or(0)
+    }
+}
+
+// Response envelope for paginated lists
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub total: usize,
+    pub limit: usize,
+    pub offset: usize,
+    pub order: SortOrder,
+    pub items: Vec<T>,
+}
+
+impl<T> Page<T> {
+    pub fn new(total: usize, query: &PageQuery, items: Vec<T>) -> Self {
+        Page { total, limit: query.limit(), offset: query.offset(), order: query.order, items }
+    }
+
+    // Page through a list that is fully in memory and sorted in descending order
+    pub fn from_sorted(mut sorted: Vec<T>, query: &PageQuery) -> Self {
+        let total = sorted.len();
+        if query.order == SortOrder::Asc {
+            sorted.reverse();
+        }
+        let items = sorted.into_iter().skip(query.offset()).take(query.limit()).collect();
+        Page::new(total, query, items)
+    }
+}
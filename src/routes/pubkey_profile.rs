@@ -0,0 +1,57 @@
+use warp::Filter;
+use warp::reply::{json, with_status};
+use serde::Serialize;
+use serde_json::json;
+use log::error;
+use crate::db::Collections;
+use crate::profile::{self, PubkeyBlock, PubkeyProfile};
+use crate::routes::pagination::{Page, PageQuery, SortOrder};
+
+#[derive(Serialize)]
+struct ProfileResponse {
+    #[serde(flatten)]
+    profile: PubkeyProfile,
+    blocks: Page<PubkeyBlock>,
+}
+
+pub fn get_pubkey_profile(
+    collections: Collections,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("pubkeys" / String)
+        .and(warp::get())
+        .and(warp::query::<PageQuery>())
+        .and(warp::any().map(move || collections.clone()))
+        .and_then(handle_get_pubkey_profile)
+}
+
+async fn handle_get_pubkey_profile(
+    pubkey: String,
+    query: PageQuery,
+    collections: Collections,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let profile = match profile::load_profile(&collections, &pubkey).await {
+        Ok(Some(profile)) => profile,
+        Ok(None) => {
+            return Ok(with_status(json(&json!({"error": "Pubkey not found"})), warp::http::StatusCode::NOT_FOUND));
+        }
+        Err(e) => {
+            error!("Error querying MongoDB: {:?}", e);
+            let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
+            return Ok(with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR));
+        }
+    };
+
+    // The block list is paginated with the usual limit, offset and order parameters
+    let descending = query.order == SortOrder::Desc;
+    match profile::page_pubkey_blocks(&collections.blocks, &pubkey, query.offset(), query.limit(), descending).await {
+        Ok((total, items)) => {
+            let response = json(&ProfileResponse { profile, blocks: Page::new(total, &query, items) });
+            Ok(with_status(response, warp::http::StatusCode::OK))
+        }
+        Err(e) => {
+            error!("Error querying MongoDB: {:?}", e);
+            let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
+            Ok(with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
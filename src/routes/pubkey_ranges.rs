@@ -3,15 +3,15 @@ use mongodb::{Collection, bson::{doc, Document}};
 use warp::reply::{json, with_status};
 use serde_json::json;
 use log::{error, info};
-use std::collections::HashMap;
-use futures_util::StreamExt;
-use crate::models::{Block, Entry, FinalHash};
+use crate::leaderboard;
+use crate::routes::pagination::{Page, PageQuery};
 
 pub fn get_blocks_in_range(
     collection: Collection<Document>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("blocks" / i32 / i32)
         .and(warp::get())
+        .and(warp::query::<PageQuery>())
         .and(with_collection(collection))
         .and_then(handle_get_blocks_in_range)
 }
@@ -25,6 +25,7 @@ fn with_collection(
 async fn handle_get_blocks_in_range(
     start_id: i32,
     end_id: i32,
+    query: PageQuery,
     collection: Collection<Document>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     if start_id > end_id {
@@ -40,48 +41,18 @@ async fn handle_get_blocks_in_range(
 
     info!("Querying MongoDB with filter: {:?}", filter);
 
-    match collection.find(filter, None).await {
-        Ok(mut cursor) => {
-            let mut pubkey_counts: HashMap<String, u32> = HashMap::new();
-            while let Some(result) = cursor.next().await {
-                match result {
-                    Ok(document) => {
-                        info!("Fetched document: {:?}", document);
-                        match mongodb::bson::from_document::<Block>(document) {
-                            Ok(block) => {
-                                for entry in block.entries {
-                                    for pubkey in entry.final_hashes.iter().flat_map(|fh| &fh.pubkeys) {
-                                        *pubkey_counts.entry(pubkey.clone()).or_insert(0) += 1;
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                error!("Error deserializing block: {:?}", e);
-                                let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
-                                return Ok(with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR));
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        error!("Error fetching document: {:?}", e);
-                        let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
-                        return Ok(with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR));
-                    }
-                }
-            }
-
-            if pubkey_counts.is_empty() {
+    match leaderboard::tally_pubkeys(&collection, filter).await {
+        Ok(tallies) => {
+            if tallies.is_empty() {
                 info!("No pubkeys found in the specified range.");
                 return Ok(with_status(json(&json!({"message": "No pubkeys found"})), warp::http::StatusCode::NOT_FOUND));
             }
 
-            // Convert the HashMap to a Vec of tuples for sorting
-            let mut sorted_pubkey_counts: Vec<(String, u32)> = pubkey_counts.into_iter().collect();
-            // Sort the pubkey_counts by count in descending order
-            sorted_pubkey_counts.sort_by(|a, b| b.1.cmp(&a.1));
+            // Sort the pubkey counts by count in descending order
+            let sorted_pubkey_counts = leaderboard::rank(tallies);
 
-            // Convert the sorted Vec back to a JSON object
-            let response = json(&sorted_pubkey_counts);
+            // Return the requested page of the sorted list
+            let response = json(&Page::from_sorted(sorted_pubkey_counts, &query));
             Ok(with_status(response, warp::http::StatusCode::OK))
         }
         Err(e) => {
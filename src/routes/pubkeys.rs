@@ -1,18 +1,18 @@
 use warp::Filter;
-use mongodb::{Collection, bson::{doc, Document}};
+use mongodb::{Collection, bson::Document};
 use warp::reply::{json, with_status};
 use serde_json::json;
 use log::{error};
-use std::collections::HashMap;
-use futures_util::StreamExt;
-use crate::models::Block;
+use crate::leaderboard;
+use crate::routes::pagination::{Page, PageQuery, SortOrder};
 
 pub fn get_all_pubkey_counts(
-    collection: Collection<Document>,
+    pubkey_stats: Collection<Document>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("pubkeys")
         .and(warp::get())
-        .and(with_collection(collection))
+        .and(warp::query::<PageQuery>())
+        .and(with_collection(pubkey_stats))
         .and_then(handle_get_all_pubkey_counts)
 }
 
@@ -23,45 +23,14 @@ fn with_collection(
 }
 
 async fn handle_get_all_pubkey_counts(
-    collection: Collection<Document>,
+    query: PageQuery,
+    pubkey_stats: Collection<Document>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let filter = doc! {};
-    match collection.find(filter, None).await {
-        Ok(mut cursor) => {
-            let mut pubkey_counts: HashMap<String, u32> = HashMap::new();
-            while let Some(result) = cursor.next().await {
-                match result {
-                    Ok(document) => {
-                        match mongodb::bson::from_document::<Block>(document) {
-                            Ok(block) => {
-                                for entry in block.entries {
-                                    for pubkey in entry.final_hashes.iter().flat_map(|fh| &fh.pubkeys) {
-                                        *pubkey_counts.entry(pubkey.clone()).or_insert(0) += 1;
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                error!("Error deserializing block: {:?}", e);
-                                let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
-                                return Ok(with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR));
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        error!("Error fetching document: {:?}", e);
-                        let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
-                        return Ok(with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR));
-                    }
-                }
-            }
-
-            // Convert the HashMap to a Vec of tuples for sorting
-            let mut sorted_pubkey_counts: Vec<(String, u32)> = pubkey_counts.into_iter().collect();
-            // Sort the pubkey_counts by count in descending order
-            sorted_pubkey_counts.sort_by(|a, b| b.1.cmp(&a.1));
-
-            // Convert the sorted Vec back to a JSON object
-            let response = json(&sorted_pubkey_counts);
+    // pubkey_stats is maintained at ingest time, so this only reads the requested page
+    let descending = query.order == SortOrder::Desc;
+    match leaderboard::page_pubkey_stats(&pubkey_stats, query.offset(), query.limit(), descending).await {
+        Ok((total, rows)) => {
+            let response = json(&Page::new(total, &query, rows));
             Ok(with_status(response, warp::http::StatusCode::OK))
         }
         Err(e) => {
@@ -0,0 +1,87 @@
+use warp::Filter;
+use mongodb::bson::oid::ObjectId;
+use warp::reply::{json, with_status};
+use serde::Serialize;
+use serde_json::json;
+use log::error;
+use crate::db::Collections;
+use crate::leaderboard::LeaderboardRow;
+use crate::routes::pagination::{Page, PageQuery, SortOrder};
+use crate::snapshots::{self, SnapshotInfo};
+
+#[derive(Serialize)]
+struct SnapshotResponse {
+    #[serde(flatten)]
+    info: SnapshotInfo,
+    rows: Page<LeaderboardRow>,
+}
+
+// GET /leaderboard/snapshots?limit&offset&order, newest first by default
+pub fn get_snapshots(
+    collections: Collections,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("leaderboard" / "snapshots")
+        .and(warp::get())
+        .and(warp::query::<PageQuery>())
+        .and(warp::any().map(move || collections.clone()))
+        .and_then(handle_get_snapshots)
+}
+
+// GET /leaderboard/snapshots/{id}?limit&offset&order, a page of the snapshot's ranked rows
+pub fn get_snapshot_by_id(
+    collections: Collections,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("leaderboard" / "snapshots" / String)
+        .and(warp::get())
+        .and(warp::query::<PageQuery>())
+        .and(warp::any().map(move || collections.clone()))
+        .and_then(handle_get_snapshot_by_id)
+}
+
+async fn handle_get_snapshots(
+    query: PageQuery,
+    collections: Collections,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let descending = query.order == SortOrder::Desc;
+    match snapshots::list_snapshots(&collections.snapshots, query.offset(), query.limit(), descending).await {
+        Ok((total, items)) => {
+            let response = json(&Page::new(total, &query, items));
+            Ok(with_status(response, warp::http::StatusCode::OK))
+        }
+        Err(e) => Ok(internal_error(e)),
+    }
+}
+
+async fn handle_get_snapshot_by_id(
+    id: String,
+    query: PageQuery,
+    collections: Collections,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let Ok(id) = ObjectId::parse_str(&id) else {
+        return Ok(with_status(json(&json!({"error": "Invalid snapshot ID"})), warp::http::StatusCode::BAD_REQUEST));
+    };
+
+    let info = match snapshots::find_snapshot(&collections.snapshots, id).await {
+        Ok(Some(info)) => info,
+        Ok(None) => {
+            return Ok(with_status(json(&json!({"error": "Snapshot not found"})), warp::http::StatusCode::NOT_FOUND));
+        }
+        Err(e) => return Ok(internal_error(e)),
+    };
+
+    let descending = query.order == SortOrder::Desc;
+    match snapshots::snapshot_rows(&collections.snapshot_rows, id, query.offset(), query.limit(), descending).await {
+        Ok(rows) => {
+            let total = info.pubkeys as usize;
+            let response = json(&SnapshotResponse { info, rows: Page::new(total, &query, rows) });
+            Ok(with_status(response, warp::http::StatusCode::OK))
+        }
+        Err(e) => Ok(internal_error(e)),
+    }
+}
+
+fn internal_error(e: mongodb::error::Error) -> warp::reply::WithStatus<warp::reply::Json> {
+    error!("Error querying MongoDB: {:?}", e);
+    let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
+    with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+}
@@ -0,0 +1,75 @@
+use warp::Filter;
+use mongodb::{Collection, bson::Document};
+use warp::reply::{json, with_status};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use log::error;
+use crate::leaderboard::{self, LeaderboardRow};
+use crate::routes::pagination::{Page, PageQuery};
+use crate::windows::{self, TimeWindow, WindowError};
+
+#[derive(Debug, Deserialize)]
+pub struct WindowQuery {
+    // RFC 3339 or Unix seconds
+    pub since: Option<String>,
+    pub until: Option<String>,
+    // Named window ending at `until`, e.g. 1h, 24h or 7d
+    pub window: Option<String>,
+}
+
+impl WindowQuery {
+    pub fn resolve(&self) -> Result<TimeWindow, WindowError> {
+        TimeWindow::resolve(self.since.as_deref(), self.until.as_deref(), self.window.as_deref())
+    }
+}
+
+#[derive(Serialize)]
+struct WindowResponse {
+    #[serde(flatten)]
+    window: TimeWindow,
+    #[serde(flatten)]
+    page: Page<LeaderboardRow>,
+}
+
+pub fn get_window_leaderboard(
+    hourly_stats: Collection<Document>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("leaderboard" / "window")
+        .and(warp::get())
+        .and(warp::query::<WindowQuery>())
+        .and(warp::query::<PageQuery>())
+        .and(with_collection(hourly_stats))
+        .and_then(handle_get_window_leaderboard)
+}
+
+fn with_collection(
+    collection: Collection<Document>,
+) -> impl Filter<Extract = (Collection<Document>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || collection.clone())
+}
+
+async fn handle_get_window_leaderboard(
+    window_query: WindowQuery,
+    query: PageQuery,
+    hourly_stats: Collection<Document>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let window = match window_query.resolve() {
+        Ok(window) => window,
+        Err(e) => {
+            return Ok(with_status(json(&json!({"error": e.to_string()})), warp::http::StatusCode::BAD_REQUEST));
+        }
+    };
+
+    match windows::window_votes(&hourly_stats, &window).await {
+        Ok(votes) => {
+            let ranked = leaderboard::rank_votes(votes);
+            let response = json(&WindowResponse { window, page: Page::from_sorted(ranked, &query) });
+            Ok(with_status(response, warp::http::StatusCode::OK))
+        }
+        Err(e) => {
+            error!("Error querying MongoDB: {:?}", e);
+            let internal_error_reply = json(&json!({"error": "Internal Server Error"}));
+            Ok(with_status(internal_error_reply, warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
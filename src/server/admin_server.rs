@@ -0,0 +1,34 @@
+use std::sync::Arc;
+use warp::Filter;
+
+use crate::config::Config;
+use crate::db::Collections;
+use crate::failed::RetryJob;
+use crate::source::BlockSource;
+
+use crate::routes::{
+    rewind_ingest_cursor, reset_ingest_cursor, retry_failed_blocks, get_retry_status, enqueue_gaps,
+};
+
+// Endpoints that change ingestion state, served on their own listener so they are not exposed
+// wherever the public API is
+pub async fn run_admin_server(collections: Collections, source: Arc<dyn BlockSource>, config: Config) {
+    let settings = &config.server;
+    let retry_job = Arc::new(RetryJob::default());
+
+    let rewind_cursor_route = rewind_ingest_cursor(collections.state.clone(), config.fetch.clone());
+    let reset_cursor_route = reset_ingest_cursor(collections.state.clone());
+    let enqueue_gaps_route = enqueue_gaps(collections.clone(), config.fetch.clone());
+    let retry_status_route = get_retry_status(retry_job.clone());
+    let retry_failed_route = retry_failed_blocks(collections, source, config.retry.clone(), retry_job);
+
+    let admin_routes = rewind_cursor_route
+        .or(reset_cursor_route)
+        .or(enqueue_gaps_route)
+        .or(retry_status_route)
+        .or(retry_failed_route);
+
+    warp::serve(admin_routes)
+        .run((settings.admin_bind_address, settings.admin_port))
+        .await;
+}
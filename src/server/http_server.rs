@@ -1,22 +1,64 @@
+use std::sync::Arc;
 use warp::Filter;
-use mongodb::{Collection, bson::Document};
+
+use crate::config::Config;
+use crate::db::Collections;
+use crate::metrics::Metrics;
 
 // Import route handlers from the crate root
-use crate::routes::{get_block_by_id, get_all_pubkey_counts, get_blocks_in_range};
+use crate::routes::{
+    get_block_by_id, get_all_pubkey_counts, get_blocks_in_range, get_pubkey_profile,
+    get_global_neighborhood, get_range_neighborhood, get_accuracy_leaderboard, get_forks, get_final_hash,
+    get_window_leaderboard, get_range_diff, get_window_diff, get_snapshots, get_snapshot_by_id,
+    get_ingest_cursor, get_failed_blocks, get_gaps, get_metrics,
+};
+
+// Read-only API; the endpoints that change ingestion state are on the admin listener
+pub async fn run_http_server(collections: Collections, config: Config, metrics: Arc<Metrics>) {
+    let collection = collections.blocks.clone();
 
-pub async fn run_http_server(collection: Collection<Document>) {
     // Define the routes for the REST API
     let block_route = get_block_by_id(collection.clone());
-    let pubkey_counts_route = get_all_pubkey_counts(collection.clone());
-    let pubkey_ranges = get_blocks_in_range(collection);
+    let pubkey_counts_route = get_all_pubkey_counts(collections.pubkey_stats.clone());
+    let pubkey_ranges = get_blocks_in_range(collection.clone());
+    let pubkey_profile_route = get_pubkey_profile(collections.clone());
+    let global_neighborhood_route = get_global_neighborhood(collections.pubkey_stats.clone());
+    let range_neighborhood_route = get_range_neighborhood(collection.clone());
+    let accuracy_route = get_accuracy_leaderboard(collections.pubkey_stats.clone());
+    let forks_route = get_forks(collections.forks.clone());
+    let final_hash_route = get_final_hash(collection.clone());
+    let window_route = get_window_leaderboard(collections.hourly_stats.clone());
+    let range_diff_route = get_range_diff(collection.clone());
+    let window_diff_route = get_window_diff(collections.hourly_stats.clone());
+    let snapshots_route = get_snapshots(collections.clone());
+    let snapshot_route = get_snapshot_by_id(collections.clone());
+    let cursor_route = get_ingest_cursor(collections.state.clone());
+    let gaps_route = get_gaps(collection.clone(), config.fetch.clone());
+    let failed_route = get_failed_blocks(collections.failed.clone());
+    let metrics_route = get_metrics(metrics);
 
     // Combine the routes
     let api_routes = block_route
         .or(pubkey_counts_route)
-        .or(pubkey_ranges);
+        .or(pubkey_ranges)
+        .or(pubkey_profile_route)
+        .or(global_neighborhood_route)
+        .or(range_neighborhood_route)
+        .or(accuracy_route)
+        .or(forks_route)
+        .or(final_hash_route)
+        .or(window_route)
+        .or(range_diff_route)
+        .or(window_diff_route)
+        .or(snapshots_route)
+        .or(snapshot_route)
+        .or(cursor_route)
+        .or(gaps_route)
+        .or(failed_route)
+        .or(metrics_route);
 
-    // Serve the HTTP server on port 3031 (or another port of your choice)
+    // Serve the HTTP server on the configured port
     warp::serve(api_routes)
-        .run(([0, 0, 0, 0], 3031))
+        .run((config.server.bind_address, config.server.http_port))
         .await;
 }
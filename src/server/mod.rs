@@ -1,2 +1,3 @@
+pub mod admin_server;
 pub mod http_server;
 pub mod ws_server;
\ No newline at end of file
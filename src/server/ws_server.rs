@@ -1,17 +1,29 @@
-use std::sync::{Arc, Mutex};
-use tokio::sync::broadcast;
+use std::time::Duration;
 
+use crate::config::Config;
+use crate::events::EventBus;
 use crate::ws;
 
-pub async fn run_ws_server(tx: broadcast::Sender<String>) {
-    // Create the clients vector
-    let clients = Arc::new(Mutex::new(Vec::new()));
+pub async fn run_ws_server(events: EventBus, config: Config) {
+    let settings = &config.server;
+    // Keep idle connections alive and let clients notice dead ones
+    if settings.heartbeat_interval_secs > 0 {
+        let events = events.clone();
+        let period = Duration::from_secs(settings.heartbeat_interval_secs);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                events.heartbeat();
+            }
+        });
+    }
 
-    // Create the WebSocket filter with the Sender and Clients
-    let ws_route = ws::ws_filter(clients.clone(), tx.clone());
+    // Create the WebSocket filter with the event bus
+    let ws_route = ws::ws_filter(events, config.events.lag_policy);
 
-    // Serve the WebSocket server on port 3030 (or another port of your choice)
+    // Serve the WebSocket server on the configured port
     warp::serve(ws_route)
-        .run(([0, 0, 0, 0], 3030))
+        .run((settings.bind_address, settings.ws_port))
         .await;
 }
@@ -0,0 +1,175 @@
+use futures_util::StreamExt;
+use mongodb::{Collection, options::FindOptions, bson::{doc, oid::ObjectId, Bson, DateTime, Document}};
+use serde::{Deserialize, Serialize};
+
+use crate::db::Collections;
+use crate::leaderboard::{self, LeaderboardRow, PubkeyStats};
+
+// Status of a snapshot header while its rows are written. Headers written before snapshots had a
+// status are complete.
+const PENDING: &str = "pending";
+const COMPLETE: &str = "complete";
+
+// A stored snapshot; its ranked rows live in the snapshot rows collection
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    #[serde(rename = "_id", serialize_with = "mongodb::bson::serde_helpers::serialize_object_id_as_hex_string")]
+    pub id: ObjectId,
+    #[serde(rename = "takenAt", serialize_with = "mongodb::bson::serde_helpers::serialize_bson_datetime_as_rfc3339_string")]
+    pub taken_at: DateTime,
+    // Last block ingested before the snapshot was taken, if it was triggered by ingestion
+    #[serde(rename = "blockId")]
+    pub block_id: Option<i64>,
+    pub pubkeys: i64,
+    pub votes: i64,
+}
+
+// Copy the current global leaderboard from pubkey_stats into a new snapshot. The header goes in
+// first as pending, so rows are never left without one; listings skip it until the rows are
+// written. A failed snapshot is removed again.
+pub async fn take_snapshot(collections: &Collections, block_id: Option<u32>) -> Result<SnapshotInfo, mongodb::error::Error> {
+    let id = ObjectId::new();
+    let taken_at = DateTime::now();
+    let block_id = block_id.map(i64::from);
+    let header = doc! { "_id": id, "takenAt": taken_at, "blockId": block_id, "pubkeys": 0_i64, "votes": 0_i64, "status": PENDING };
+    collections.snapshots.insert_one(header, None).await?;
+
+    match write_rows(collections, id).await {
+        Ok((pubkeys, votes)) => {
+            let update = doc! { "$set": { "pubkeys": pubkeys, "votes": votes, "status": COMPLETE } };
+            collections.snapshots.update_one(doc! { "_id": id }, update, None).await?;
+            Ok(SnapshotInfo { id, taken_at, block_id, pubkeys, votes })
+        }
+        Err(e) => {
+            if let Err(e) = collections.snapshot_rows.delete_many(doc! { "snapshotId": id }, None).await {
+                eprintln!("Error removing the rows of failed snapshot {}: {}", id, e);
+            } else if let Err(e) = collections.snapshots.delete_one(doc! { "_id": id }, None).await {
+                eprintln!("Error removing failed snapshot {}: {}", id, e);
+            }
+            Err(e)
+        }
+    }
+}
+
+// Write the ranked rows of snapshot `id`, returning the number of pubkeys and their total votes
+async fn write_rows(collections: &Collections, id: ObjectId) -> Result<(i64, i64), mongodb::error::Error> {
+    let options = FindOptions::builder().sort(doc! { "votes": -1, "_id": 1 }).build();
+    let mut cursor = collections.pubkey_stats.find(doc! { "votes": { "$gt": 0 } }, options).await?;
+    let mut rows = Vec::new();
+    let mut pubkeys = 0;
+    let mut votes = 0;
+    while let Some(document) = cursor.next().await {
+        let stats: PubkeyStats = mongodb::bson::from_document(document?)?;
+        pubkeys += 1;
+        votes += stats.votes as i64;
+        rows.push(row_document(id, &LeaderboardRow { rank: pubkeys as usize, pubkey: stats.pubkey, votes: stats.votes }));
+        if rows.len() >= leaderboard::STATS_BATCH_SIZE {
+            collections.snapshot_rows.insert_many(std::mem::take(&mut rows), None).await?;
+        }
+    }
+    if !rows.is_empty() {
+        collections.snapshot_rows.insert_many(rows, None).await?;
+    }
+    Ok((pubkeys, votes))
+}
+
+// Snapshots whose rows are all written
+fn complete_filter() -> Document {
+    doc! { "status": { "$ne": PENDING } }
+}
+
+// One page of the snapshot headers ordered by time taken, with the total number of snapshots
+pub async fn list_snapshots(
+    snapshots: &Collection<Document>,
+    offset: usize,
+    limit: usize,
+    descending: bool,
+) -> Result<(usize, Vec<SnapshotInfo>), mongodb::error::Error> {
+    let total = snapshots.count_documents(complete_filter(), None).await? as usize;
+    let options = FindOptions::builder()
+        .sort(doc! { "takenAt": if descending { -1 } else { 1 } })
+        .skip(offset as u64)
+        .limit(limit as i64)
+        .build();
+    let mut cursor = snapshots.find(complete_filter(), options).await?;
+    let mut items = Vec::new();
+    while let Some(document) = cursor.next().await {
+        items.push(mongodb::bson::from_document(document?)?);
+    }
+    Ok((total, items))
+}
+
+pub async fn find_snapshot(snapshots: &Collection<Document>, id: ObjectId) -> Result<Option<SnapshotInfo>, mongodb::error::Error> {
+    let mut filter = complete_filter();
+    filter.insert("_id", id);
+    match snapshots.find_one(filter, None).await? {
+        Some(document) => Ok(Some(mongodb::bson::from_document(document)?)),
+        None => Ok(None),
+    }
+}
+
+// One page of a snapshot's ranked rows; `descending` starts at rank 1
+pub async fn snapshot_rows(
+    snapshot_rows: &Collection<Document>,
+    id: ObjectId,
+    offset: usize,
+    limit: usize,
+    descending: bool,
+) -> Result<Vec<LeaderboardRow>, mongodb::error::Error> {
+    let options = FindOptions::builder()
+        .sort(doc! { "rank": if descending { 1 } else { -1 } })
+        .skip(offset as u64)
+        .limit(limit as i64)
+        .build();
+    let mut cursor = snapshot_rows.find(doc! { "snapshotId": id }, options).await?;
+    let mut rows = Vec::new();
+    while let Some(document) = cursor.next().await {
+        rows.push(row_from_document(&document?));
+    }
+    Ok(rows)
+}
+
+// Counts are stored as Int64 so they read back the same way whatever their size
+fn row_document(snapshot_id: ObjectId, row: &LeaderboardRow) -> Document {
+    doc! {
+        "snapshotId": snapshot_id,
+        "rank": row.rank as i64,
+        "pubkey": &row.pubkey,
+        "votes": row.votes as i64,
+    }
+}
+
+// Rows written by earlier versions may hold Int32 counts
+fn row_from_document(document: &Document) -> LeaderboardRow {
+    LeaderboardRow {
+        rank: document.get("rank").and_then(bson_count).unwrap_or_default() as usize,
+        pubkey: document.get_str("pubkey").unwrap_or_default().to_string(),
+        votes: document.get("votes").and_then(bson_count).unwrap_or_default() as u32,
+    }
+}
+
+fn bson_count(value: &Bson) -> Option<i64> {
+    match value {
+        Bson::Int32(count) => Some(i64::from(*count)),
+        Bson::Int64(count) => Some(*count),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_row_round_trips() {
+        let row = LeaderboardRow { rank: 3, pubkey: "pk".to_string(), votes: 42 };
+        let document = row_document(ObjectId::new(), &row);
+        assert_eq!(row_from_document(&document), row);
+    }
+
+    #[test]
+    fn reads_int32_counts() {
+        let document = doc! { "snapshotId": ObjectId::new(), "rank": 1i32, "pubkey": "pk", "votes": 7i32 };
+        assert_eq!(row_from_document(&document), LeaderboardRow { rank: 1, pubkey: "pk".to_string(), votes: 7 });
+    }
+}
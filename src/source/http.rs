@@ -0,0 +1,138 @@
+use async_trait::async_trait;
+
+use crate::api::FetchError;
+use crate::models::Block;
+use super::{BlockFetch, BlockSource};
+
+// Upper bound on doubling steps when searching for the tip from scratch
+const TIP_SEARCH_MAX_DOUBLINGS: u32 = 24;
+
+pub struct HttpSource {
+    client: reqwest::Client,
+    url: String,
+    start_block_id: i32,
+    block_increment: i32,
+}
+
+impl HttpSource {
+    pub fn new(url: &str, start_block_id: i32, block_increment: i32) -> Self {
+        HttpSource {
+            client: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            start_block_id,
+            block_increment,
+        }
+    }
+
+    fn step_to_block_id(&self, step: i64) -> Option<i32> {
+        i32::try_from(self.start_block_id as i64 + step * self.block_increment as i64).ok()
+    }
+
+    // Errors are passed on rather than read as a missing block, which would put the tip too
+    // early. A block that cannot be decoded is still there.
+    async fn is_found(&self, block_id: i32) -> Result<bool, FetchError> {
+        match self.fetch_block(block_id).await {
+            Ok(fetched) => Ok(matches!(fetched, BlockFetch::Found(_))),
+            Err(FetchError::DecodeError(_)) => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[async_trait]
+impl BlockSource for HttpSource {
+    async fn fetch_raw_block(&self, block_id: i32) -> Result<BlockFetch, FetchError> {
+        let url = format!("{}/{}", self.url, block_id);
+
+        let response = self.client.get(&url).send().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(BlockFetch::Missing);
+        }
+        if !response.status().is_success() {
+            return Err(FetchError::NonSuccessStatus(response.status()));
+        }
+
+        // Parse the JSON data
+        let body = response.text().await?;
+        let block: Block = serde_json::from_str(&body)?;
+        Ok(BlockFetch::Found(block))
+    }
+
+    // The upstream has no "latest" endpoint, so find the tip by doubling the stride from the
+    // start block ID until a block is missing, then binary searching between the last hit and
+    // the first miss
+    async fn latest_block_id(&self) -> Result<Option<i32>, FetchError> {
+        if !self.is_found(self.start_block_id).await? {
+            return Ok(None);
+        }
+
+        let mut found: i64 = 0;
+        let mut missing: Option<i64> = None;
+        let mut stride: i64 = 1;
+        for _ in 0..TIP_SEARCH_MAX_DOUBLINGS {
+            let step = found + stride;
+            let Some(block_id) = self.step_to_block_id(step) else { break };
+            if self.is_found(block_id).await? {
+                found = step;
+            } else {
+                missing = Some(step);
+                break;
+            }
+            stride *= 2;
+        }
+
+        if let Some(mut missing) = missing {
+            while missing - found > 1 {
+                let mid = found + (missing - found) / 2;
+                let Some(block_id) = self.step_to_block_id(mid) else { break };
+                if self.is_found(block_id).await? {
+                    found = mid;
+                } else {
+                    missing = mid;
+                }
+            }
+        }
+        Ok(self.step_to_block_id(found))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use warp::Filter;
+    use super::*;
+    use crate::source::MockSource;
+
+    // Serve the mock source's blocks over HTTP, failing with 503 for `unavailable`
+    async fn serve(blocks: MockSource, unavailable: Option<i32>) -> String {
+        let blocks = Arc::new(blocks);
+        let route = warp::path!(i32).then(move |block_id: i32| {
+            let blocks = blocks.clone();
+            async move {
+                let (body, status) = match blocks.fetch_raw_block(block_id).await {
+                    _ if unavailable == Some(block_id) => (String::new(), warp::http::StatusCode::SERVICE_UNAVAILABLE),
+                    Ok(BlockFetch::Found(block)) => (serde_json::to_string(&block).unwrap(), warp::http::StatusCode::OK),
+                    _ => (String::new(), warp::http::StatusCode::NOT_FOUND),
+                };
+                warp::reply::with_status(body, status)
+            }
+        });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn finds_the_tip() {
+        let url = serve(MockSource::synthetic(1000, 10, 37), None).await;
+        let source = HttpSource::new(&url, 1000, 10);
+        assert_eq!(source.latest_block_id().await.unwrap(), Some(1360));
+    }
+
+    #[tokio::test]
+    async fn server_errors_fail_the_tip_search() {
+        let url = serve(MockSource::synthetic(1000, 10, 37), Some(1030)).await;
+        let source = HttpSource::new(&url, 1000, 10);
+        assert!(matches!(source.latest_block_id().await, Err(FetchError::NonSuccessStatus(_))));
+    }
+}
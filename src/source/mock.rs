@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use async_trait::async_trait;
+
+use crate::api::FetchError;
+use crate::models::{Block, Entry, FinalHash};
+use super::{BlockFetch, BlockSource};
+
+// Number of distinct voters the synthetic blocks are drawn from
+const MOCK_VOTERS: usize = 16;
+// Entries per synthetic block
+const MOCK_ENTRIES_PER_BLOCK: usize = 4;
+
+// In-process source; blocks can be added while the fetcher is running
+#[derive(Default)]
+pub struct MockSource {
+    blocks: Mutex<BTreeMap<i32, Block>>,
+}
+
+impl MockSource {
+    pub fn new(blocks: Vec<Block>) -> Self {
+        let source = MockSource::default();
+        for block in blocks {
+            source.insert_block(block);
+        }
+        source
+    }
+
+    // Deterministic blocks on the step sequence, with a minority final hash every third entry
+    pub fn synthetic(start_block_id: i32, block_increment: i32, count: usize) -> Self {
+        let blocks = (0..count)
+            .map(|n| {
+                let block_id = start_block_id + n as i32 * block_increment;
+                let entries = (0..MOCK_ENTRIES_PER_BLOCK)
+                    .map(|e| {
+                        let voters: Vec<String> = (0..MOCK_VOTERS)
+                            .filter(|v| (v + n + e) % 3 != 0)
+                            .map(|v| format!("mockpubkey{:02}", v))
+                            .collect();
+                        let split = if (n + e) % 3 == 0 { voters.len() / 4 } else { 0 };
+                        let (minority, majority) = voters.split_at(split);
+                        let mut final_hashes = vec![mock_final_hash(block_id, e, "a", majority)];
+                        if !minority.is_empty() {
+                            final_hashes.push(mock_final_hash(block_id, e, "b", minority));
+                        }
+                        Entry { block_id: format!("{}-{}", block_id, e), final_hashes }
+                    })
+                    .collect();
+                Block { block_id: block_id as u32, entries }
+            })
+            .collect();
+        MockSource::new(blocks)
+    }
+
+    pub fn insert_block(&self, block: Block) {
+        self.blocks.lock().unwrap().insert(block.block_id as i32, block);
+    }
+
+}
+
+fn mock_final_hash(block_id: i32, entry: usize, variant: &str, pubkeys: &[String]) -> FinalHash {
+    FinalHash {
+        final_hash: format!("mockhash-{}-{}-{}", block_id, entry, variant),
+        count: pubkeys.len() as u32,
+        pubkeys: pubkeys.to_vec(),
+    }
+}
+
+#[async_trait]
+impl BlockSource for MockSource {
+    async fn fetch_raw_block(&self, block_id: i32) -> Result<BlockFetch, FetchError> {
+        Ok(match self.blocks.lock().unwrap().get(&block_id) {
+            Some(block) => BlockFetch::Found(block.clone()),
+            None => BlockFetch::Missing,
+        })
+    }
+
+    async fn latest_block_id(&self) -> Result<Option<i32>, FetchError> {
+        Ok(self.blocks.lock().unwrap().keys().next_back().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn synthetic_blocks_follow_the_step_sequence() {
+        let source = MockSource::synthetic(1000, 10, 5);
+        assert_eq!(source.latest_block_id().await.unwrap(), Some(1040));
+        assert!(matches!(source.fetch_block(1005).await.unwrap(), BlockFetch::Missing));
+
+        let BlockFetch::Found(block) = source.fetch_block(1000).await.unwrap() else {
+            panic!("block 1000 should exist");
+        };
+        assert_eq!(block.entries.len(), MOCK_ENTRIES_PER_BLOCK);
+        // The first entry of the first block splits its voters across two final hashes
+        assert_eq!(block.entries[0].final_hashes.len(), 2);
+        assert!(block.entries[0].consensus_hash().is_some());
+    }
+
+    #[tokio::test]
+    async fn inserted_blocks_replace_earlier_versions() {
+        let source = MockSource::synthetic(1000, 10, 1);
+        let entry = Entry { block_id: "1000-0".to_string(), final_hashes: vec![mock_final_hash(1000, 0, "c", &["p1".to_string()])] };
+        source.insert_block(Block { block_id: 1000, entries: vec![entry] });
+        let BlockFetch::Found(block) = source.fetch_block(1000).await.unwrap() else {
+            panic!("block 1000 should exist");
+        };
+        assert_eq!(block.entries.len(), 1);
+        assert_eq!(block.entries[0].final_hashes[0].final_hash, "mockhash-1000-0-c");
+    }
+
+    #[tokio::test]
+    async fn empty_blocks_count_as_missing() {
+        let source = MockSource::synthetic(1000, 10, 1);
+        source.insert_block(Block { block_id: 1010, entries: Vec::new() });
+        assert!(matches!(source.fetch_block(1010).await.unwrap(), BlockFetch::Missing));
+        assert!(matches!(source.fetch_raw_block(1010).await.unwrap(), BlockFetch::Found(_)));
+    }
+}
@@ -0,0 +1,80 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::api::FetchError;
+use crate::config::Config;
+use crate::models::Block;
+
+pub mod http;
+pub mod replay;
+pub mod mock;
+
+pub use http::HttpSource;
+pub use replay::ReplaySource;
+pub use mock::MockSource;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    // The live upstream HTTP API
+    Http,
+    // Recorded JSON/NDJSON block dumps on disk
+    Replay,
+    // Synthetic blocks generated in-process
+    Mock,
+}
+
+impl SourceKind {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "http" => Some(SourceKind::Http),
+            "replay" => Some(SourceKind::Replay),
+            "mock" => Some(SourceKind::Mock),
+            _ => None,
+        }
+    }
+}
+
+pub enum BlockFetch {
+    Found(Block),
+    Missing,
+}
+
+// Where blocks are ingested from
+#[async_trait]
+pub trait BlockSource: Send + Sync {
+    // Fetch a single block as the source stores it
+    async fn fetch_raw_block(&self, block_id: i32) -> Result<BlockFetch, FetchError>;
+
+    // Fetch a single block; `Missing` means the source does not have it (yet). The upstream
+    // answers with an empty block for IDs it has not produced yet, so a block without entries
+    // counts as missing from every source and follow mode finds the tip the same way on each.
+    async fn fetch_block(&self, block_id: i32) -> Result<BlockFetch, FetchError> {
+        Ok(match self.fetch_raw_block(block_id).await? {
+            BlockFetch::Found(block) if block.entries.is_empty() => BlockFetch::Missing,
+            fetch => fetch,
+        })
+    }
+
+    // The highest block ID the source can currently serve, if it has any
+    async fn latest_block_id(&self) -> Result<Option<i32>, FetchError>;
+}
+
+// Build the block source selected by upstream.kind
+pub fn from_config(config: &Config) -> Result<Arc<dyn BlockSource>, FetchError> {
+    let upstream = &config.upstream;
+    Ok(match upstream.kind {
+        SourceKind::Http => Arc::new(HttpSource::new(
+            &upstream.url,
+            config.fetch.start_block_id,
+            config.fetch.block_increment,
+        )),
+        SourceKind::Replay => Arc::new(ReplaySource::load(&upstream.replay_path)?),
+        SourceKind::Mock => Arc::new(MockSource::synthetic(
+            config.fetch.start_block_id,
+            config.fetch.block_increment,
+            upstream.mock_blocks,
+        )),
+    })
+}
@@ -0,0 +1,70 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use async_trait::async_trait;
+
+use crate::api::FetchError;
+use crate::models::Block;
+use super::{BlockFetch, BlockSource};
+
+// Serves blocks recorded to disk, either as JSON (a single block or an array of blocks) or as
+// NDJSON with one block per line, e.g. the output of `export blocks`. A directory is read file
+// by file.
+pub struct ReplaySource {
+    blocks: BTreeMap<i32, Block>,
+}
+
+impl ReplaySource {
+    pub fn load(path: &Path) -> Result<Self, FetchError> {
+        let mut blocks = BTreeMap::new();
+        if path.is_dir() {
+            let mut files: Vec<_> = std::fs::read_dir(path)?
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .map(|entry| entry.path())
+                .filter(|file| file.is_file())
+                .collect();
+            files.sort();
+            for file in files {
+                read_blocks(&file, &mut blocks)?;
+            }
+        } else {
+            read_blocks(path, &mut blocks)?;
+        }
+        Ok(ReplaySource { blocks })
+    }
+}
+
+fn read_blocks(path: &Path, blocks: &mut BTreeMap<i32, Block>) -> Result<(), FetchError> {
+    let contents = std::fs::read_to_string(path)?;
+    let parsed: Vec<Block> = match serde_json::from_str::<serde_json::Value>(&contents) {
+        Ok(serde_json::Value::Array(values)) => values
+            .into_iter()
+            .map(serde_json::from_value)
+            .collect::<Result<_, _>>()?,
+        Ok(value) => vec![serde_json::from_value(value)?],
+        // Not a single JSON document, so treat it as NDJSON
+        Err(_) => contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?,
+    };
+    for block in parsed {
+        blocks.insert(block.block_id as i32, block);
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl BlockSource for ReplaySource {
+    async fn fetch_raw_block(&self, block_id: i32) -> Result<BlockFetch, FetchError> {
+        Ok(match self.blocks.get(&block_id) {
+            Some(block) => BlockFetch::Found(block.clone()),
+            None => BlockFetch::Missing,
+        })
+    }
+
+    async fn latest_block_id(&self) -> Result<Option<i32>, FetchError> {
+        Ok(self.blocks.keys().next_back().copied())
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::BTreeSet;
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::events::{Event, EventBus, EventKind, EVENT_VERSION};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+    // block_ingested events
+    Blocks,
+    // leaderboard_update deltas for the top of the global leaderboard, after a leaderboard_snapshot
+    Leaderboard,
+    // pubkey_votes events, narrowed down to the subscribed pubkeys
+    Pubkeys,
+    // fork_detected events for entries with more than one final hash
+    Forks,
+    // ingest_error events
+    Errors,
+}
+
+impl Topic {
+    fn of(kind: EventKind) -> Option<Topic> {
+        match kind {
+            EventKind::BlockIngested => Some(Topic::Blocks),
+            EventKind::LeaderboardUpdate | EventKind::LeaderboardSnapshot => Some(Topic::Leaderboard),
+            EventKind::PubkeyVotes => Some(Topic::Pubkeys),
+            EventKind::ForkDetected => Some(Topic::Forks),
+            EventKind::IngestError => Some(Topic::Errors),
+            EventKind::Heartbeat
+            | EventKind::Lagged
+            | EventKind::Subscriptions
+            | EventKind::ResumeFailed
+            | EventKind::Error => None,
+        }
+    }
+}
+
+// A command sent by a WebSocket client
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ClientCommand {
+    Subscribe {
+        #[serde(default)]
+        topics: Vec<Topic>,
+        #[serde(default)]
+        pubkeys: Vec<String>,
+    },
+    Unsubscribe {
+        #[serde(default)]
+        topics: Vec<Topic>,
+        #[serde(default)]
+        pubkeys: Vec<String>,
+    },
+    // Replay the events missed since a sequence number or block ID
+    Resume {
+        seq: Option<u64>,
+        block_id: Option<u32>,
+    },
+}
+
+// What one client wants to receive
+#[derive(Debug, Serialize)]
+pub struct Subscription {
+    pub topics: BTreeSet<Topic>,
+    pub pubkeys: BTreeSet<String>,
+}
+
+impl Default for Subscription {
+    // New connections get every topic that needs no arguments, as they did before topics existed
+    fn default() -> Self {
+        Subscription {
+            topics: [Topic::Blocks, Topic::Leaderboard, Topic::Forks, Topic::Errors].into_iter().collect(),
+            pubkeys: BTreeSet::new(),
+        }
+    }
+}
+
+impl Subscription {
+    // Apply a subscribe or unsubscribe command and return the messages to send back: the reply,
+    // followed by a leaderboard snapshot when the command subscribed to the leaderboard.
+    // `delivered` is the seq of the last event the client received.
+    pub fn apply(&mut self, command: ClientCommand, events: &EventBus, delivered: u64) -> Vec<String> {
+        let had_leaderboard = self.topics.contains(&Topic::Leaderboard);
+        match command {
+            ClientCommand::Subscribe { topics, pubkeys } => {
+                // Asking for pubkeys implies the pubkeys topic
+                if !pubkeys.is_empty() {
+                    self.topics.insert(Topic::Pubkeys);
+                }
+                self.topics.extend(topics);
+                self.pubkeys.extend(pubkeys);
+            }
+            ClientCommand::Unsubscribe { topics, pubkeys } => {
+                for topic in &topics {
+                    self.topics.remove(topic);
+                }
+                for pubkey in &pubkeys {
+                    self.pubkeys.remove(pubkey);
+                }
+            }
+            ClientCommand::Resume { .. } => return Vec::new(),
+        }
+
+        let mut replies = vec![control_message(EventKind::Subscriptions, delivered, serde_json::json!(self))];
+        if !had_leaderboard && self.topics.contains(&Topic::Leaderboard) {
+            replies.push(events.leaderboard_snapshot().to_json());
+        }
+        replies
+    }
+
+    // The JSON to send for `event`, or None when the client is not subscribed to it
+    pub fn render(&self, event: &Event) -> Option<String> {
+        let Some(topic) = Topic::of(event.kind) else {
+            return Some(event.to_json());
+        };
+        if !self.topics.contains(&topic) {
+            return None;
+        }
+        if topic != Topic::Pubkeys {
+            return Some(event.to_json());
+        }
+
+        // Only pass on the rows of the pubkeys this client follows
+        let rows: Vec<&serde_json::Value> = event.payload["pubkeys"]
+            .as_array()?
+            .iter()
+            .filter(|row| row["pubkey"].as_str().is_some_and(|pubkey| self.pubkeys.contains(pubkey)))
+            .collect();
+        if rows.is_empty() {
+            return None;
+        }
+        let mut filtered = event.clone();
+        filtered.payload = serde_json::json!({ "pubkeys": rows });
+        Some(filtered.to_json())
+    }
+}
+
+// A reply to a client command in the event envelope. It is not published, so it takes the seq
+// of the last event the client received and refers to no block.
+pub fn control_message(kind: EventKind, delivered: u64, payload: serde_json::Value) -> String {
+    Event { v: EVENT_VERSION, kind, seq: delivered, block_id: None, ts: DateTime::now(), payload }.to_json()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: EventKind, payload: serde_json::Value) -> Event {
+        Event { v: EVENT_VERSION, kind, seq: 1, block_id: Some(1), ts: DateTime::now(), payload }
+    }
+
+    fn pubkey_votes() -> Event {
+        event(
+            EventKind::PubkeyVotes,
+            serde_json::json!({ "pubkeys": [{ "pubkey": "a", "votes": 1 }, { "pubkey": "b", "votes": 2 }] }),
+        )
+    }
+
+    #[test]
+    fn default_subscription_skips_pubkey_votes() {
+        let subscription = Subscription::default();
+        assert!(subscription.render(&event(EventKind::BlockIngested, serde_json::json!({}))).is_some());
+        assert!(subscription.render(&pubkey_votes()).is_none());
+    }
+
+    #[test]
+    fn heartbeats_and_lag_notices_ignore_topics() {
+        let subscription = Subscription { topics: BTreeSet::new(), pubkeys: BTreeSet::new() };
+        assert!(subscription.render(&event(EventKind::Heartbeat, serde_json::json!({}))).is_some());
+        assert!(subscription.render(&event(EventKind::Lagged, serde_json::json!({}))).is_some());
+        assert!(subscription.render(&event(EventKind::LeaderboardSnapshot, serde_json::json!({}))).is_none());
+    }
+
+    #[test]
+    fn pubkey_votes_keep_only_followed_rows() {
+        let subscription = Subscription {
+            topics: [Topic::Pubkeys].into_iter().collect(),
+            pubkeys: ["b".to_string()].into_iter().collect(),
+        };
+        let rendered: serde_json::Value = serde_json::from_str(&subscription.render(&pubkey_votes()).unwrap()).unwrap();
+        assert_eq!(rendered["payload"], serde_json::json!({ "pubkeys": [{ "pubkey": "b", "votes": 2 }] }));
+
+        let unrelated = Subscription { pubkeys: ["c".to_string()].into_iter().collect(), ..subscription };
+        assert!(unrelated.render(&pubkey_votes()).is_none());
+    }
+
+    #[test]
+    fn replies_use_the_event_envelope() {
+        let reply: serde_json::Value = serde_json::from_str(&control_message(EventKind::ResumeFailed, 42, serde_json::json!({ "error": "gone" }))).unwrap();
+        assert_eq!(reply["v"], EVENT_VERSION);
+        assert_eq!(reply["type"], "resume_failed");
+        assert_eq!(reply["seq"], 42);
+        assert!(reply["blockId"].is_null());
+        assert!(reply["ts"].is_string());
+        assert_eq!(reply["payload"], serde_json::json!({ "error": "gone" }));
+    }
+
+    #[test]
+    fn parses_client_commands() {
+        let command: ClientCommand = serde_json::from_str(r#"{"action":"subscribe","topics":["forks"],"pubkeys":["a"]}"#).unwrap();
+        assert!(matches!(command, ClientCommand::Subscribe { ref topics, ref pubkeys } if topics == &[Topic::Forks] && pubkeys == &["a"]));
+        assert!(serde_json::from_str::<ClientCommand>(r#"{"action":"dance"}"#).is_err());
+    }
+}
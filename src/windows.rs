@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use futures_util::StreamExt;
+use mongodb::{Collection, options::AggregateOptions, bson::{doc, Bson, DateTime, Document}};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::db;
+use crate::indexes;
+use crate::leaderboard;
+use crate::models::Block;
+
+// Width of one pubkey_hourly_stats bucket
+const HOUR_MS: i64 = 60 * 60 * 1000;
+
+#[derive(Error, Debug)]
+pub enum WindowError {
+    #[error("Invalid timestamp {0:?}, expected RFC 3339 or Unix seconds")]
+    InvalidTimestamp(String),
+    #[error("Invalid window {0:?}, expected a number followed by h, d or w such as 24h or 7d")]
+    InvalidWindow(String),
+    #[error("Either since or window is required")]
+    MissingStart,
+    #[error("since must be before until")]
+    Empty,
+}
+
+// Half-open time range [since, until)
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TimeWindow {
+    #[serde(with = "mongodb::bson::serde_helpers::bson_datetime_as_rfc3339_string")]
+    pub since: DateTime,
+    #[serde(with = "mongodb::bson::serde_helpers::bson_datetime_as_rfc3339_string")]
+    pub until: DateTime,
+}
+
+impl TimeWindow {
+    // Build a window from since/until timestamps or a named window such as 24h ending at `until`
+    // (or now). Bucket granularity is one hour, so `since` is rounded down and `until` up to the
+    // hour, and the resolved window shows the hours actually counted.
+    pub fn resolve(since: Option<&str>, until: Option<&str>, window: Option<&str>) -> Result<Self, WindowError> {
+        let until = match until {
+            Some(until) => parse_timestamp(until)?,
+            None => DateTime::now(),
+        };
+        let since = match (since, window) {
+            (Some(since), _) => parse_timestamp(since)?,
+            (None, Some(window)) => DateTime::from_millis(until.timestamp_millis().saturating_sub(parse_window(window)?)),
+            (None, None) => return Err(WindowError::MissingStart),
+        };
+        if since >= until {
+            return Err(WindowError::Empty);
+        }
+        let until = match until.timestamp_millis() % HOUR_MS {
+            0 => until,
+            _ => DateTime::from_millis(hour_bucket(until).timestamp_millis().saturating_add(HOUR_MS)),
+        };
+        Ok(TimeWindow { since: hour_bucket(since), until })
+    }
+
+    // The window of the same length directly before this one
+    pub fn preceding(&self) -> TimeWindow {
+        let length = self.until.timestamp_millis().saturating_sub(self.since.timestamp_millis());
+        TimeWindow { since: DateTime::from_millis(self.since.timestamp_millis().saturating_sub(length)), until: self.since }
+    }
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime, WindowError> {
+    if let Ok(seconds) = value.parse::<i64>() {
+        return seconds
+            .checked_mul(1000)
+            .map(DateTime::from_millis)
+            .ok_or_else(|| WindowError::InvalidTimestamp(value.to_string()));
+    }
+    DateTime::parse_rfc3339_str(value).map_err(|_| WindowError::InvalidTimestamp(value.to_string()))
+}
+
+// Length of a named window in milliseconds
+fn parse_window(value: &str) -> Result<i64, WindowError> {
+    let invalid = || WindowError::InvalidWindow(value.to_string());
+    let (amount, hours) = [("h", 1), ("d", 24), ("w", 24 * 7)]
+        .iter()
+        .find_map(|(unit, hours)| value.strip_suffix(unit).map(|amount| (amount, *hours)))
+        .ok_or_else(invalid)?;
+    let amount: i64 = amount.parse().ok().filter(|amount| (1..=100_000).contains(amount)).ok_or_else(invalid)?;
+    Ok(amount * hours * HOUR_MS)
+}
+
+// Start of the hour `time` falls in
+pub fn hour_bucket(time: DateTime) -> DateTime {
+    DateTime::from_millis(time.timestamp_millis().div_euclid(HOUR_MS) * HOUR_MS)
+}
+
+// When a stored block was first observed; documents written before observedAt was recorded
+// fall back to updatedAt and then to the creation time of their ObjectId
+pub fn observed_at(document: &Document) -> Option<DateTime> {
+    document
+        .get_datetime("observedAt")
+        .or_else(|_| document.get_datetime("updatedAt"))
+        .ok()
+        .copied()
+        .or_else(|| document.get_object_id("_id").ok().map(|id| id.timestamp()))
+}
+
+// Add (sign = 1) or remove (sign = -1) a block's votes to the bucket of `hour`, at most once
+// per `marker`
+pub async fn apply_block_to_hourly(
+    hourly_stats: &Collection<Document>,
+    block: &Block,
+    hour: DateTime,
+    sign: i32,
+    marker: &str,
+) -> Result<(), leaderboard::UpdateError> {
+    let updates: Vec<(Document, Document)> = leaderboard::block_vote_counts(block)
+        .into_iter()
+        .map(|(pubkey, counts)| (
+            doc! { "hour": hour, "pubkey": pubkey },
+            doc! { "$inc": { "votes": sign as i64 * counts.votes as i64 } },
+        ))
+        .collect();
+    let initial = (sign > 0).then(|| doc! { "votes": 0 });
+    leaderboard::send_marked_updates(hourly_stats, updates, initial, marker, block.block_id).await
+}
+
+// Remove the marker of a finished apply_block_to_hourly from the buckets it touched
+pub async fn clear_hourly_marker(
+    hourly_stats: &Collection<Document>,
+    block: &Block,
+    hour: DateTime,
+    marker: &str,
+) -> Result<(), leaderboard::UpdateError> {
+    let targets = leaderboard::block_vote_counts(block)
+        .into_keys()
+        .map(|pubkey| doc! { "hour": hour, "pubkey": pubkey })
+        .collect();
+    leaderboard::clear_marker(hourly_stats, targets, marker, block.block_id).await
+}
+
+// Recompute the hourly buckets from scratch out of the stored blocks, swapping the result in
+// once it is complete
+pub async fn rebuild_hourly_stats(
+    blocks: &Collection<Document>,
+    hourly_stats: &Collection<Document>,
+) -> Result<usize, mongodb::error::Error> {
+    let mut buckets: HashMap<(i64, String), u32> = HashMap::new();
+    let mut cursor = blocks.find(doc! {}, None).await?;
+    while let Some(document) = cursor.next().await {
+        let document = document?;
+        let hour = hour_bucket(observed_at(&document).unwrap_or_else(DateTime::now)).timestamp_millis();
+        let block: Block = mongodb::bson::from_document(document)?;
+        for (pubkey, counts) in leaderboard::block_vote_counts(&block) {
+            *buckets.entry((hour, pubkey.to_string())).or_insert(0) += counts.votes;
+        }
+    }
+    let total = buckets.len();
+
+    let staging = db::staging_collection(hourly_stats).await?;
+    indexes::create_indexes(&staging, &indexes::hourly_stats_indexes()).await?;
+    let documents: Vec<Document> = buckets
+        .into_iter()
+        .map(|((hour, pubkey), votes)| doc! { "hour": DateTime::from_millis(hour), "pubkey": pubkey, "votes": votes })
+        .collect();
+    for batch in documents.chunks(leaderboard::STATS_BATCH_SIZE) {
+        staging.insert_many(batch, None).await?;
+    }
+    db::swap_in(&staging, hourly_stats).await?;
+    Ok(total)
+}
+
+// Votes per pubkey within `window`, summed over its hourly buckets
+pub async fn window_votes(
+    hourly_stats: &Collection<Document>,
+    window: &TimeWindow,
+) -> Result<HashMap<String, u32>, mongodb::error::Error> {
+    let pipeline = vec![
+        doc! { "$match": { "hour": { "$gte": window.since, "$lt": window.until } } },
+        doc! { "$group": { "_id": "$pubkey", "votes": { "$sum": "$votes" } } },
+        doc! { "$match": { "votes": { "$gt": 0 } } },
+    ];
+    let options = AggregateOptions::builder().allow_disk_use(true).build();
+    let mut cursor = hourly_stats.aggregate(pipeline, options).await?;
+    let mut votes = HashMap::new();
+    while let Some(document) = cursor.next().await {
+        let document = document?;
+        let Ok(pubkey) = document.get_str("_id") else { continue };
+        let count = match document.get("votes") {
+            Some(Bson::Int32(count)) => *count as u32,
+            Some(Bson::Int64(count)) => *count as u32,
+            _ => 0,
+        };
+        votes.insert(pubkey.to_string(), count);
+    }
+    Ok(votes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_timestamps_that_overflow() {
+        assert!(matches!(parse_timestamp("99999999999999999"), Err(WindowError::InvalidTimestamp(_))));
+        assert!(matches!(parse_timestamp("-99999999999999999"), Err(WindowError::InvalidTimestamp(_))));
+    }
+
+    #[test]
+    fn parses_named_windows() {
+        assert_eq!(parse_window("24h").unwrap(), 24 * HOUR_MS);
+        assert_eq!(parse_window("7d").unwrap(), 7 * 24 * HOUR_MS);
+        assert_eq!(parse_window("2w").unwrap(), 14 * 24 * HOUR_MS);
+        for invalid in ["", "h", "0h", "-1d", "1y", "1.5h", "100001h", "d7"] {
+            assert!(matches!(parse_window(invalid), Err(WindowError::InvalidWindow(_))), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn resolves_a_window_ending_at_until() {
+        let window = TimeWindow::resolve(None, Some("2024-01-02T00:00:00Z"), Some("1d")).unwrap();
+        assert_eq!(window.since, DateTime::parse_rfc3339_str("2024-01-01T00:00:00Z").unwrap());
+        assert_eq!(window.preceding().since, DateTime::parse_rfc3339_str("2023-12-31T00:00:00Z").unwrap());
+        assert!(matches!(TimeWindow::resolve(None, None, None), Err(WindowError::MissingStart)));
+        assert!(matches!(TimeWindow::resolve(Some("20"), Some("10"), None), Err(WindowError::Empty)));
+    }
+
+    #[test]
+    fn rounds_to_whole_hours() {
+        let window = TimeWindow::resolve(Some("1700000100"), Some("1700003700"), None).unwrap();
+        assert_eq!(window.since.timestamp_millis(), 1_699_999_200_000);
+        assert_eq!(window.until.timestamp_millis(), 1_700_006_400_000);
+    }
+}
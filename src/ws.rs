@@ -1,49 +1,186 @@
 use futures_util::{StreamExt, SinkExt};
-use std::sync::{Arc, Mutex};
-use tokio::sync::broadcast::{Sender};
+use futures_util::stream::SplitSink;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
 use warp::ws::{Message, WebSocket};
 use warp::Filter;
 
-type Clients = Arc<Mutex<Vec<tokio::sync::broadcast::Sender<String>>>>;
+use crate::events::{Event, EventBus, EventKind, LagPolicy, ResumePoint};
+use crate::metrics::Metrics;
+use crate::subscriptions::{control_message, ClientCommand, Subscription};
 
-pub async fn handle_ws_connection(ws: WebSocket, clients: Clients, tx: Sender<String>) {
-    let (mut ws_tx, mut ws_rx) = ws.split();
-    let mut rx = tx.subscribe(); // Create a new receiver for this connection
+// Query parameters a reconnecting client may pass on the WebSocket URL
+#[derive(Debug, Deserialize)]
+pub struct ResumeQuery {
+    // Continue after this sequence number
+    pub resume_from: Option<u64>,
+    // Continue after the last event about this block
+    pub resume_from_block: Option<u32>,
+}
+
+impl ResumeQuery {
+    fn point(&self) -> Option<ResumePoint> {
+        self.resume_from.map(ResumePoint::Seq).or(self.resume_from_block.map(ResumePoint::BlockId))
+    }
+}
+
+struct Connection {
+    ws_tx: SplitSink<WebSocket, Message>,
+    subscription: Subscription,
+    // Highest sequence number sent so far; live events up to it were already replayed
+    delivered: u64,
+}
+
+impl Connection {
+    async fn send(&mut self, text: String) -> bool {
+        self.ws_tx.send(Message::text(text)).await.is_ok()
+    }
+
+    async fn send_event(&mut self, event: &Event) -> bool {
+        if event.kind != EventKind::Heartbeat {
+            if event.seq <= self.delivered {
+                return true;
+            }
+            self.delivered = event.seq;
+        }
+        match self.subscription.render(event) {
+            Some(text) => self.send(text).await,
+            None => true,
+        }
+    }
+
+    // Replay what the client missed; on failure it is told why and gets a fresh leaderboard snapshot
+    async fn resume(&mut self, events: &EventBus, point: ResumePoint) -> bool {
+        match events.replay(point).await {
+            Ok(replayed) => {
+                // Replaying from an earlier point than already delivered sends those events again
+                self.delivered = 0;
+                for event in &replayed {
+                    if !self.send_event(event).await {
+                        return false;
+                    }
+                }
+                true
+            }
+            Err(e) => {
+                let error = control_message(EventKind::ResumeFailed, self.delivered, serde_json::json!({ "error": e.to_string() }));
+                self.send(error).await && self.send_snapshot(events).await
+            }
+        }
+    }
 
-    {
-        let mut clients = clients.lock().unwrap();
-        clients.push(tx.clone());
+    // Start over from the current leaderboard; clients not subscribed to it only skip ahead
+    async fn send_snapshot(&mut self, events: &EventBus) -> bool {
+        let snapshot = events.leaderboard_snapshot();
+        self.delivered = self.delivered.max(snapshot.seq);
+        match self.subscription.render(&snapshot) {
+            Some(text) => self.send(text).await,
+            None => true,
+        }
     }
 
-    tokio::spawn(async move {
-        while let Ok(msg) = rx.recv().await {
-            if ws_tx.send(Message::text(msg)).await.is_err() {
-                break;
+    // The client fell behind the broadcast channel and missed `skipped` events. Returns whether
+    // the connection stays open.
+    async fn lagged(&mut self, events: &EventBus, skipped: u64, policy: LagPolicy) -> bool {
+        let metrics = events.metrics();
+        Metrics::increment(&metrics.ws_lagged, 1);
+        Metrics::increment(&metrics.ws_lagged_events, skipped);
+
+        let notice = events.lagged(self.delivered, skipped, policy);
+        match policy {
+            LagPolicy::Resync => self.send(notice.to_json()).await && self.send_snapshot(events).await,
+            LagPolicy::Disconnect => {
+                Metrics::increment(&metrics.ws_lag_disconnects, 1);
+                if self.send(notice.to_json()).await {
+                    let _ = self.ws_tx.send(Message::close_with(1008u16, "lagged")).await;
+                }
+                false
             }
         }
-    });
+    }
+}
+
+pub async fn handle_ws_connection(ws: WebSocket, events: EventBus, resume: ResumeQuery, lag_policy: LagPolicy) {
+    let connections = &events.metrics().ws_connections;
+    Metrics::increment(connections, 1);
+    serve_connection(ws, &events, resume, lag_policy).await;
+    connections.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+}
+
+async fn serve_connection(ws: WebSocket, events: &EventBus, resume: ResumeQuery, lag_policy: LagPolicy) {
+    let (ws_tx, mut ws_rx) = ws.split();
+    let mut rx = events.subscribe(); // Create a new receiver for this connection
+    let mut connection = Connection { ws_tx, subscription: Subscription::default(), delivered: 0 };
+
+    // A reconnecting client catches up on what it missed; anyone else starts from the current
+    // leaderboard, which the default subscription includes
+    let started = match resume.point() {
+        Some(point) => connection.resume(events, point).await,
+        None => connection.send_snapshot(events).await,
+    };
+    if !started {
+        return;
+    }
 
-    while ws_rx.next().await.is_some() {}
+    loop {
+        tokio::select! {
+            received = rx.recv() => {
+                let sent = match received {
+                    Ok(event) => connection.send_event(&event).await,
+                    Err(RecvError::Lagged(skipped)) => connection.lagged(events, skipped, lag_policy).await,
+                    Err(RecvError::Closed) => false,
+                };
+                if !sent {
+                    break;
+                }
+            }
+            incoming = ws_rx.next() => {
+                // Commands arrive as text messages
+                let Some(Ok(message)) = incoming else { break };
+                if message.is_close() {
+                    break;
+                }
+                if let Ok(text) = message.to_str() {
+                    if !handle_command(&mut connection, events, text).await {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
 
-    // Remove the client upon disconnection
-    let mut clients = clients.lock().unwrap();
-    clients.retain(|client| {
-        // In the current setup, you would need a way to determine if the client is still active.
-        // This example assumes all clients are active, as `Sender` does not have a `is_closed` method.
-        // For actual connection tracking, you would need to maintain a list of WebSocket connections
-        // and handle them accordingly.
-        true // Replace with actual client status check if possible
-    });
+async fn handle_command(connection: &mut Connection, events: &EventBus, text: &str) -> bool {
+    let replies = match serde_json::from_str::<ClientCommand>(text) {
+        Ok(ClientCommand::Resume { seq, block_id }) => {
+            let Some(point) = seq.map(ResumePoint::Seq).or(block_id.map(ResumePoint::BlockId)) else {
+                let error = control_message(EventKind::Error, connection.delivered, serde_json::json!({ "error": "resume needs seq or block_id" }));
+                return connection.send(error).await;
+            };
+            return connection.resume(events, point).await;
+        }
+        Ok(command) => connection.subscription.apply(command, events, connection.delivered),
+        Err(e) => {
+            let payload = serde_json::json!({ "error": format!("Invalid command: {}", e) });
+            vec![control_message(EventKind::Error, connection.delivered, payload)]
+        }
+    };
+    for reply in replies {
+        if !connection.send(reply).await {
+            return false;
+        }
+    }
+    true
 }
 
 pub fn ws_filter(
-    clients: Clients,
-    tx: Sender<String>,
+    events: EventBus,
+    lag_policy: LagPolicy,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::ws()
-        .map(move |ws: warp::ws::Ws| {
-            let clients = clients.clone();
-            let tx = tx.clone(); // Clone the sender to create a new receiver for each connection
-            ws.on_upgrade(move |socket| handle_ws_connection(socket, clients, tx))
+        .and(warp::query::<ResumeQuery>())
+        .map(move |ws: warp::ws::Ws, resume: ResumeQuery| {
+            let events = events.clone(); // Each connection subscribes on its own
+            ws.on_upgrade(move |socket| handle_ws_connection(socket, events, resume, lag_policy))
         })
 }